@@ -0,0 +1,172 @@
+use crate::engine::dijkstra::DijkstraMap;
+use crate::engine::{
+    self, Blocking, Direction, Fov, FovAlgorithm, GameRng, Occupancy, PathOptions, PathResult,
+    PATH_BUDGET,
+};
+use crate::ecs::{EntityId, Position, World};
+use crate::entity::Tile;
+use rand::prelude::*;
+
+/// What a npc is currently doing
+#[derive(Copy, Clone, PartialEq)]
+pub enum Behaviour {
+    Wander,
+    Hunt,
+    Flee,
+    Search,
+}
+
+/// Memory of a npc from one turn to the next
+#[derive(Copy, Clone)]
+pub struct Ai {
+    pub behaviour: Behaviour,
+    pub last_seen: Option<(i32, i32)>,
+}
+
+impl Ai {
+    pub fn new() -> Ai {
+        Ai {
+            behaviour: Behaviour::Wander,
+            last_seen: None,
+        }
+    }
+}
+
+/// Distance fields to the player, shared by every npc
+pub struct Fields {
+    chase: DijkstraMap,
+    flee: DijkstraMap,
+    // Reused by each npc looking for the player, so only the cells it saw are cleared
+    sight: Fov,
+}
+
+impl Fields {
+    pub fn new(w: i32, h: i32) -> Fields {
+        Fields {
+            chase: DijkstraMap::new(w, h, false),
+            flee: DijkstraMap::new(w, h, false),
+            sight: Fov::new(w, h),
+        }
+    }
+
+    /// Follow the player, nothing is computed while the player stays put
+    pub fn update(&mut self, level_map: &[Tile], player: Position) {
+        if self.chase.set_goals(level_map, &[(player.x, player.y)]) {
+            self.flee = self.chase.inverted(level_map);
+        }
+    }
+}
+
+/// Update the npc's behaviour and choose where it goes this turn
+pub fn take_turn(
+    world: &mut World,
+    id: EntityId,
+    player: EntityId,
+    level_map: &[Tile],
+    occupancy: &Occupancy,
+    fields: &mut Fields,
+    rng: &mut GameRng,
+) -> Direction {
+    let (npc, player, profile) = match (
+        world.positions.get(id),
+        world.positions.get(player),
+        world.kinds.get(id),
+    ) {
+        (Some(&npc), Some(&player), Some(kind)) => (npc, player, kind.def()),
+        _ => return Direction::None,
+    };
+    let hurt = world
+        .healths
+        .get(id)
+        .is_some_and(|h| h.hp * 100 < h.max_hp * profile.flee_below);
+    let ai = match world.ais.get_mut(id) {
+        Some(ai) => ai,
+        None => return Direction::None,
+    };
+
+    let (w, h) = fields.chase.size();
+    let sees_player = can_see(&mut fields.sight, npc, player, profile.sight, level_map);
+    if sees_player {
+        ai.last_seen = Some((player.x, player.y));
+    }
+
+    ai.behaviour = if sees_player && hurt {
+        Behaviour::Flee
+    } else if sees_player {
+        Behaviour::Hunt
+    } else if ai.last_seen.is_some() {
+        Behaviour::Search
+    } else {
+        Behaviour::Wander
+    };
+
+    match ai.behaviour {
+        Behaviour::Hunt => {
+            // Other npc may stand in the way, then walk around them
+            let free = |x, y| (x, y) == (player.x, player.y) || !occupancy.is_occupied(x, y);
+            match fields.chase.downhill(npc.x, npc.y, free) {
+                Some(step) => engine::orientation(step),
+                None => approach(npc, (player.x, player.y), level_map, occupancy, w, h),
+            }
+        }
+        Behaviour::Search => {
+            // Go where the player was last seen, then give up
+            let target = ai.last_seen.unwrap_or((npc.x, npc.y));
+            if (npc.x, npc.y) == target {
+                ai.last_seen = None;
+                ai.behaviour = Behaviour::Wander;
+                wander(rng)
+            } else {
+                approach(npc, target, level_map, occupancy, w, h)
+            }
+        }
+        Behaviour::Flee => {
+            let step = fields.flee.downhill(npc.x, npc.y, |x, y| !occupancy.is_occupied(x, y));
+            engine::orientation(step.unwrap_or((0, 0)))
+        }
+        Behaviour::Wander => wander(rng),
+    }
+}
+
+fn can_see(fov: &mut Fov, npc: Position, player: Position, sight: i32, level_map: &[Tile]) -> bool {
+    if distance_sq(npc.x, npc.y, player.x, player.y) > sight * sight {
+        return false;
+    }
+    // Shadowcasting is symmetric, the npc sees the player when the player could see it
+    engine::compute_fov(fov, npc.x, npc.y, sight, level_map, FovAlgorithm::Shadowcast);
+    fov.contains(player.x, player.y)
+}
+
+/// Next step toward target, other npc are walked around
+fn approach(
+    npc: Position,
+    target: (i32, i32),
+    level_map: &[Tile],
+    occupancy: &Occupancy,
+    w: i32,
+    h: i32,
+) -> Direction {
+    let options = PathOptions {
+        blocking: Blocking::Block,
+        known_only: false,
+        budget: PATH_BUDGET,
+    };
+    match engine::path_finder((npc.x, npc.y), target, level_map, occupancy, options, w, h) {
+        PathResult::Found(path) if !path.is_empty() => {
+            engine::orientation((path[0].x - npc.x, path[0].y - npc.y))
+        }
+        // No way around, just head straight for it
+        _ => engine::orientation(((target.0 - npc.x).signum(), (target.1 - npc.y).signum())),
+    }
+}
+
+fn wander(rng: &mut GameRng) -> Direction {
+    let x: i32 = rng.gen_range(-1..=1);
+    let y: i32 = rng.gen_range(-1..=1);
+    engine::orientation((x, y))
+}
+
+/// Squared euclidean distance, enough to compare or check a range
+fn distance_sq(x1: i32, y1: i32, x2: i32, y2: i32) -> i32 {
+    (x1 - x2).pow(2) + (y1 - y2).pow(2)
+}
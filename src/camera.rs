@@ -0,0 +1,68 @@
+/// Part of the map shown on screen
+/// The view follows a world cell, without showing past the edges of a map bigger than it
+pub struct Camera {
+    // Top left cell of the view on screen
+    screen_x: i32,
+    screen_y: i32,
+    // Size of the view in cells
+    w: i32,
+    h: i32,
+    // World cell at the top left of the view, negative when the map is smaller than the view
+    x: i32,
+    y: i32,
+    map_w: i32,
+    map_h: i32,
+}
+
+impl Camera {
+    pub fn new(screen_x: i32, screen_y: i32, w: i32, h: i32) -> Camera {
+        Camera {
+            screen_x,
+            screen_y,
+            w,
+            h,
+            x: 0,
+            y: 0,
+            map_w: w,
+            map_h: h,
+        }
+    }
+
+    /// Centre the view on a world cell
+    /// A map smaller than the view is centred instead
+    pub fn center_on(&mut self, x: i32, y: i32, map_w: i32, map_h: i32) {
+        let origin = |center: i32, view: i32, map: i32| {
+            if map <= view {
+                (map - view) / 2
+            } else {
+                (center - view / 2).max(0).min(map - view)
+            }
+        };
+        self.x = origin(x, self.w, map_w);
+        self.y = origin(y, self.h, map_h);
+        self.map_w = map_w;
+        self.map_h = map_h;
+    }
+
+    /// Screen cell of a world cell, None when it is out of view
+    pub fn to_screen(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (vx, vy) = (x - self.x, y - self.y);
+        if vx < 0 || vy < 0 || vx >= self.w || vy >= self.h {
+            return None;
+        }
+        Some((self.screen_x + vx, self.screen_y + vy))
+    }
+
+    /// World cell under a screen cell, None outside the view or the map
+    pub fn to_world(&self, screen_x: i32, screen_y: i32) -> Option<(i32, i32)> {
+        let (vx, vy) = (screen_x - self.screen_x, screen_y - self.screen_y);
+        if vx < 0 || vy < 0 || vx >= self.w || vy >= self.h {
+            return None;
+        }
+        let (x, y) = (self.x + vx, self.y + vy);
+        if x < 0 || y < 0 || x >= self.map_w || y >= self.map_h {
+            return None;
+        }
+        Some((x, y))
+    }
+}
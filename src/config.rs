@@ -0,0 +1,63 @@
+use std::fs;
+use std::io;
+
+/// One `[name]` block of a config file, with its `key = value` lines
+pub struct Section {
+    pub name: String,
+    // Key, value and line number, in file order
+    pub entries: Vec<(String, String, usize)>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|e| e.0 == key)
+            .map(|e| e.1.as_str())
+    }
+}
+
+pub fn read(path: &str) -> io::Result<Vec<Section>> {
+    parse(&fs::read_to_string(path)?).map_err(|e| invalid(path, &e))
+}
+
+/// Split a config text into sections
+/// Lines starting with `#` are comments, blank lines are skipped
+pub fn parse(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push(Section {
+                name: line[1..line.len() - 1].trim().to_string(),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => return Err(format!("line {}: expected `key = value`", number)),
+        };
+        match sections.last_mut() {
+            Some(section) if !key.is_empty() => {
+                section.entries.push((key.to_string(), value.to_string(), number))
+            }
+            Some(_) => return Err(format!("line {}: missing key", number)),
+            None => return Err(format!("line {}: `{}` is outside of any section", number, key)),
+        }
+    }
+    Ok(sections)
+}
+
+/// Error for a bad config file
+pub fn invalid(path: &str, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg))
+}
@@ -0,0 +1,356 @@
+use crate::config::{self, Section};
+use crate::engine::GameRng;
+use crate::entity::{ItemKind, TileType};
+use crate::grl::cp437;
+use rand::prelude::*;
+use std::io;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tetra::graphics::Color;
+
+pub const DATA_DIR: &str = "./data";
+
+// Built-in copy of the data files, used when they can't be read
+const ENTITIES: &str = include_str!("../data/entities.cfg");
+const ITEMS: &str = include_str!("../data/items.cfg");
+const TILES: &str = include_str!("../data/tiles.cfg");
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+/// Colour with a range for each channel
+#[derive(Copy, Clone)]
+pub struct ColorRange {
+    min: [u8; 3],
+    max: [u8; 3],
+    // The same value for every channel
+    gray: bool,
+}
+
+impl ColorRange {
+    fn fixed(r: u8, g: u8, b: u8) -> ColorRange {
+        ColorRange {
+            min: [r, g, b],
+            max: [r, g, b],
+            gray: false,
+        }
+    }
+
+    pub fn pick(&self, rng: &mut GameRng) -> Color {
+        if self.gray {
+            let v = rng.gen_range(self.min[0]..=self.max[0]);
+            return Color::rgb8(v, v, v);
+        }
+        let mut c = [0; 3];
+        for (i, v) in c.iter_mut().enumerate() {
+            *v = rng.gen_range(self.min[i]..=self.max[i]);
+        }
+        Color::rgb8(c[0], c[1], c[2])
+    }
+
+    /// The colour, if the range holds only one
+    fn single(&self) -> Option<Color> {
+        if self.min == self.max {
+            Some(Color::rgb8(self.min[0], self.min[1], self.min[2]))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct EntityDef {
+    pub id: String,
+    pub name: String,
+    pub glyph: char,
+    pub color: Color,
+    pub hp: i32,
+    pub attack: i32,
+    pub defence: i32,
+    pub speed: i32,
+    pub sight: i32,
+    pub flee_below: i32,
+    pub spawn_weight: u32,
+}
+
+pub struct ItemDef {
+    pub id: String,
+    pub name: String,
+    pub kind: ItemKind,
+    pub glyph: char,
+    pub color: Color,
+    pub bonus: i32,
+    pub quantity: (i32, i32),
+    pub spawn_weight: u32,
+}
+
+pub struct TileDef {
+    pub glyph: char,
+    pub fg: ColorRange,
+    pub bg: ColorRange,
+    pub crossable: bool,
+    pub see_through: bool,
+}
+
+/// Every definition, loaded once at startup
+pub struct Registry {
+    pub entities: Vec<EntityDef>,
+    pub items: Vec<ItemDef>,
+    tiles: Vec<(TileType, TileDef)>,
+}
+
+impl Registry {
+    pub fn tile(&self, tile_type: TileType) -> Option<&TileDef> {
+        self.tiles
+            .iter()
+            .find(|(t, _)| *t == tile_type)
+            .map(|(_, def)| def)
+    }
+
+    pub fn entity_index(&self, id: &str) -> Option<usize> {
+        self.entities.iter().position(|e| e.id == id)
+    }
+
+    pub fn item_index(&self, id: &str) -> Option<usize> {
+        self.items.iter().position(|i| i.id == id)
+    }
+
+    /// Random entity to spawn, following the spawn weights
+    pub fn random_entity(&self, rng: &mut GameRng) -> Option<usize> {
+        pick_weighted(self.entities.iter().map(|e| e.spawn_weight), rng)
+    }
+
+    /// Random item to spawn, following the spawn weights
+    pub fn random_item(&self, rng: &mut GameRng) -> Option<usize> {
+        pick_weighted(self.items.iter().map(|i| i.spawn_weight), rng)
+    }
+}
+
+pub fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(builtin)
+}
+
+/// Load the data files of `dir`, the built-in copy is used if they are broken
+pub fn load(dir: &str) {
+    let registry = read_dir(dir).unwrap_or_else(|e| {
+        eprintln!("Can't load the data files: {}", e);
+        builtin()
+    });
+    // Only the first load counts
+    let _ = REGISTRY.set(registry);
+}
+
+fn read_dir(dir: &str) -> io::Result<Registry> {
+    let read = |name: &str| -> io::Result<(String, Vec<Section>)> {
+        let path = format!("{}/{}", dir, name);
+        let sections = config::read(&path)?;
+        Ok((path, sections))
+    };
+    let (entities_path, entities) = read("entities.cfg")?;
+    let (items_path, items) = read("items.cfg")?;
+    let (tiles_path, tiles) = read("tiles.cfg")?;
+    Ok(Registry {
+        entities: entities_from(&entities).map_err(|e| config::invalid(&entities_path, &e))?,
+        items: items_from(&items).map_err(|e| config::invalid(&items_path, &e))?,
+        tiles: tiles_from(&tiles).map_err(|e| config::invalid(&tiles_path, &e))?,
+    })
+}
+
+fn builtin() -> Registry {
+    let sections = |text| config::parse(text).expect("bad built-in data");
+    Registry {
+        entities: entities_from(&sections(ENTITIES)).expect("bad built-in entities"),
+        items: items_from(&sections(ITEMS)).expect("bad built-in items"),
+        tiles: tiles_from(&sections(TILES)).expect("bad built-in tiles"),
+    }
+}
+
+fn entities_from(sections: &[Section]) -> Result<Vec<EntityDef>, String> {
+    let mut entities = Vec::new();
+    for s in sections.iter() {
+        entities.push(EntityDef {
+            id: s.name.clone(),
+            name: s.get("name").unwrap_or(&s.name).to_string(),
+            glyph: glyph(s)?,
+            color: fixed_color(s, "color", ColorRange::fixed(150, 150, 150))?,
+            hp: at_least_one(s, "hp", 1)?,
+            attack: value(s, "attack", 1)?,
+            defence: value(s, "defence", 0)?,
+            // A speed of 0 would never let the entity act, and the game wait for it
+            speed: at_least_one(s, "speed", 10)?,
+            sight: value(s, "sight", 10)?,
+            flee_below: value(s, "flee_below", 0)?,
+            spawn_weight: value(s, "spawn_weight", 0)?,
+        });
+    }
+    if !entities.iter().any(|e| e.id == "player") {
+        return Err("no `[player]` section".to_string());
+    }
+    Ok(entities)
+}
+
+fn items_from(sections: &[Section]) -> Result<Vec<ItemDef>, String> {
+    let mut items = Vec::new();
+    for s in sections.iter() {
+        let kind = s.get("kind").unwrap_or("");
+        let kind = ItemKind::from_code(kind)
+            .ok_or_else(|| format!("[{}] unknown kind `{}`", s.name, kind))?;
+        items.push(ItemDef {
+            id: s.name.clone(),
+            name: s.get("name").unwrap_or(&s.name).to_string(),
+            kind,
+            glyph: glyph(s)?,
+            color: fixed_color(s, "color", ColorRange::fixed(150, 150, 150))?,
+            bonus: value(s, "bonus", 0)?,
+            quantity: match s.get("quantity") {
+                Some(v) => range(v)
+                    .filter(|&(a, b)| a <= b)
+                    .ok_or_else(|| bad_value(s, "quantity", v))?,
+                None => (1, 1),
+            },
+            spawn_weight: value(s, "spawn_weight", 0)?,
+        });
+    }
+    Ok(items)
+}
+
+fn tiles_from(sections: &[Section]) -> Result<Vec<(TileType, TileDef)>, String> {
+    let mut tiles = Vec::new();
+    for s in sections.iter() {
+        let tile_type = TileType::from_code(&s.name)
+            .ok_or_else(|| format!("[{}] unknown tile", s.name))?;
+        tiles.push((
+            tile_type,
+            TileDef {
+                glyph: glyph(s)?,
+                fg: color(s, "fg", ColorRange::fixed(150, 100, 150))?,
+                bg: color(s, "bg", ColorRange::fixed(0, 0, 0))?,
+                crossable: value(s, "crossable", true)?,
+                see_through: value(s, "see_through", true)?,
+            },
+        ));
+    }
+    Ok(tiles)
+}
+
+fn bad_value(s: &Section, key: &str, v: &str) -> String {
+    format!("[{}] bad {} `{}`", s.name, key, v)
+}
+
+fn value<T: FromStr>(s: &Section, key: &str, default: T) -> Result<T, String> {
+    match s.get(key) {
+        Some(v) => v.parse().map_err(|_| bad_value(s, key, v)),
+        None => Ok(default),
+    }
+}
+
+fn at_least_one(s: &Section, key: &str, default: i32) -> Result<i32, String> {
+    match s.get(key) {
+        Some(v) => v.parse().ok().filter(|&n| n >= 1).ok_or_else(|| bad_value(s, key, v)),
+        None => Ok(default),
+    }
+}
+
+/// A character, or the code of a glyph in the tileset
+fn glyph(s: &Section) -> Result<char, String> {
+    let v = s.get("glyph").unwrap_or("");
+    let mut chars = v.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => v.parse().map(cp437::from_tile).map_err(|_| bad_value(s, "glyph", v)),
+    }
+}
+
+/// `r g b` where each channel may be a range, or `gray min-max`
+fn color(s: &Section, key: &str, default: ColorRange) -> Result<ColorRange, String> {
+    let v = match s.get(key) {
+        Some(v) => v,
+        None => return Ok(default),
+    };
+    let channel = |c: &str| range(c).filter(|&(a, b)| a <= b && b <= 255);
+    let tokens: Vec<&str> = v.split_whitespace().collect();
+    let mut c = ColorRange::fixed(0, 0, 0);
+    match tokens.as_slice() {
+        ["gray", r] => {
+            let (a, b) = channel(r).ok_or_else(|| bad_value(s, key, v))?;
+            c.min = [a as u8; 3];
+            c.max = [b as u8; 3];
+            c.gray = true;
+        }
+        [_, _, _] => {
+            for (i, t) in tokens.iter().enumerate() {
+                let (a, b) = channel(t).ok_or_else(|| bad_value(s, key, v))?;
+                c.min[i] = a as u8;
+                c.max[i] = b as u8;
+            }
+        }
+        _ => return Err(bad_value(s, key, v)),
+    }
+    Ok(c)
+}
+
+/// Entities and items have a single colour, ranges are only for tiles
+fn fixed_color(s: &Section, key: &str, default: ColorRange) -> Result<Color, String> {
+    let v = s.get(key).unwrap_or("");
+    color(s, key, default)?
+        .single()
+        .ok_or_else(|| format!("[{}] {} `{}` can't be a range", s.name, key, v))
+}
+
+/// `n` or `min-max`
+fn range(v: &str) -> Option<(i32, i32)> {
+    let mut parts = v.splitn(2, '-');
+    let min = parts.next()?.trim().parse().ok()?;
+    let max = match parts.next() {
+        Some(max) => max.trim().parse().ok()?,
+        None => min,
+    };
+    Some((min, max))
+}
+
+fn pick_weighted(weights: impl Iterator<Item = u32> + Clone, rng: &mut GameRng) -> Option<usize> {
+    let total: u32 = weights.clone().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = rng.gen_range(0..total);
+    for (i, w) in weights.enumerate() {
+        if roll < w {
+            return Some(i);
+        }
+        roll -= w;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entities(text: &str) -> Result<Vec<EntityDef>, String> {
+        entities_from(&config::parse(text).unwrap())
+    }
+
+    fn items(text: &str) -> Result<Vec<ItemDef>, String> {
+        items_from(&config::parse(text).unwrap())
+    }
+
+    #[test]
+    fn builtin_data_loads() {
+        builtin();
+    }
+
+    #[test]
+    fn speed_and_hp_must_be_positive() {
+        assert!(entities("[player]\nglyph = @\nspeed = 10\nhp = 5\n").is_ok());
+        assert!(entities("[player]\nglyph = @\nspeed = 0\n").is_err());
+        assert!(entities("[player]\nglyph = @\nspeed = -5\n").is_err());
+        assert!(entities("[player]\nglyph = @\nhp = 0\n").is_err());
+    }
+
+    #[test]
+    fn entity_and_item_colours_are_fixed() {
+        assert!(entities("[player]\nglyph = @\ncolor = 10 20 30\n").is_ok());
+        assert!(entities("[player]\nglyph = @\ncolor = 10-50 20 30\n").is_err());
+        assert!(items("[gold]\nkind = gold\nglyph = $\ncolor = gray 100\n").is_ok());
+        assert!(items("[gold]\nkind = gold\nglyph = $\ncolor = gray 100-200\n").is_err());
+    }
+}
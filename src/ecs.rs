@@ -0,0 +1,314 @@
+use crate::ai::Ai;
+use crate::entity::{EntityType, Item, ItemKind};
+use tetra::graphics::Color;
+
+/// Index of an entity in its world
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EntityId(usize);
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Copy, Clone)]
+pub struct Renderable {
+    pub glyph: char,
+    pub fg_color: Color,
+    // In the player's field of view
+    pub in_view: bool,
+}
+
+#[derive(Copy, Clone)]
+pub struct Health {
+    pub hp: i32,
+    pub max_hp: i32,
+}
+
+impl Health {
+    pub fn is_dead(&self) -> bool {
+        self.hp <= 0
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Combat {
+    pub attack: i32,
+    pub defence: i32,
+}
+
+/// Speed and energy, for the scheduler
+#[derive(Copy, Clone)]
+pub struct Actor {
+    pub speed: i32,
+    pub energy: i32,
+}
+
+#[derive(Clone, Default)]
+pub struct Inventory {
+    pub items: Vec<Item>,
+    pub gold: i32,
+}
+
+impl Inventory {
+    fn equipped_bonus(&self, kind: ItemKind) -> i32 {
+        self.items
+            .iter()
+            .filter(|i| i.equipped && i.kind() == kind)
+            .map(|i| i.bonus())
+            .sum()
+    }
+}
+
+/// One component for each entity, in slots indexed by the entity id
+pub struct Storage<T> {
+    slots: Vec<Option<T>>,
+}
+
+impl<T> Storage<T> {
+    fn new() -> Storage<T> {
+        Storage { slots: Vec::new() }
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.slots.get(id.0).and_then(|s| s.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        self.slots.get_mut(id.0).and_then(|s| s.as_mut())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().map(|c| (EntityId(i), c)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (EntityId, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_mut().map(|c| (EntityId(i), c)))
+    }
+
+    fn set(&mut self, id: EntityId, component: Option<T>) {
+        if self.slots.len() <= id.0 {
+            self.slots.resize_with(id.0 + 1, || None);
+        }
+        self.slots[id.0] = component;
+    }
+
+    fn take(&mut self, id: EntityId) -> Option<T> {
+        self.slots.get_mut(id.0).and_then(Option::take)
+    }
+}
+
+/// Every component of one entity
+/// Used to spawn an entity, or to move it from a world to another
+#[derive(Clone, Default)]
+pub struct Bundle {
+    pub kind: Option<EntityType>,
+    pub position: Option<Position>,
+    pub renderable: Option<Renderable>,
+    pub health: Option<Health>,
+    pub combat: Option<Combat>,
+    pub actor: Option<Actor>,
+    pub ai: Option<Ai>,
+    pub inventory: Option<Inventory>,
+    pub blocks_movement: bool,
+    pub player: bool,
+}
+
+impl Bundle {
+    /// A creature as described in the data files, with an ai
+    pub fn creature(x: i32, y: i32, kind: EntityType) -> Bundle {
+        let def = kind.def();
+        Bundle {
+            kind: Some(kind),
+            position: Some(Position { x, y }),
+            renderable: Some(Renderable {
+                glyph: def.glyph,
+                fg_color: def.color,
+                in_view: false,
+            }),
+            health: Some(Health {
+                hp: def.hp,
+                max_hp: def.hp,
+            }),
+            combat: Some(Combat {
+                attack: def.attack,
+                defence: def.defence,
+            }),
+            actor: Some(Actor {
+                speed: def.speed,
+                energy: 0,
+            }),
+            ai: Some(Ai::new()),
+            inventory: None,
+            blocks_movement: true,
+            player: false,
+        }
+    }
+
+    /// The player is a creature driven by the keyboard, with a pack
+    pub fn player(x: i32, y: i32) -> Bundle {
+        Bundle {
+            ai: None,
+            inventory: Some(Inventory::default()),
+            player: true,
+            ..Bundle::creature(x, y, EntityType::player())
+        }
+    }
+
+    /// Deeper npc are tougher
+    pub fn scale_to_depth(&mut self, depth: i32) {
+        if let Some(health) = self.health.as_mut() {
+            health.max_hp += (depth - 1) * 2;
+            health.hp = health.max_hp;
+        }
+        if let Some(combat) = self.combat.as_mut() {
+            combat.attack += (depth - 1) / 2;
+            combat.defence += (depth - 1) / 3;
+        }
+    }
+}
+
+/// Entities of one level, stored as components
+pub struct World {
+    alive: Vec<bool>,
+    pub kinds: Storage<EntityType>,
+    pub positions: Storage<Position>,
+    pub renderables: Storage<Renderable>,
+    pub healths: Storage<Health>,
+    pub combats: Storage<Combat>,
+    pub actors: Storage<Actor>,
+    pub ais: Storage<Ai>,
+    pub inventories: Storage<Inventory>,
+    pub blocks_movement: Storage<()>,
+    pub players: Storage<()>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            alive: Vec::new(),
+            kinds: Storage::new(),
+            positions: Storage::new(),
+            renderables: Storage::new(),
+            healths: Storage::new(),
+            combats: Storage::new(),
+            actors: Storage::new(),
+            ais: Storage::new(),
+            inventories: Storage::new(),
+            blocks_movement: Storage::new(),
+            players: Storage::new(),
+        }
+    }
+
+    /// Add an entity, the id of a despawned one is reused
+    pub fn spawn(&mut self, bundle: Bundle) -> EntityId {
+        let id = match self.alive.iter().position(|&a| !a) {
+            Some(i) => EntityId(i),
+            None => {
+                self.alive.push(false);
+                EntityId(self.alive.len() - 1)
+            }
+        };
+        self.alive[id.0] = true;
+        self.kinds.set(id, bundle.kind);
+        self.positions.set(id, bundle.position);
+        self.renderables.set(id, bundle.renderable);
+        self.healths.set(id, bundle.health);
+        self.combats.set(id, bundle.combat);
+        self.actors.set(id, bundle.actor);
+        self.ais.set(id, bundle.ai);
+        self.inventories.set(id, bundle.inventory);
+        self.blocks_movement.set(id, flag(bundle.blocks_movement));
+        self.players.set(id, flag(bundle.player));
+        id
+    }
+
+    /// Remove an entity, its components are given back
+    pub fn despawn(&mut self, id: EntityId) -> Bundle {
+        if let Some(alive) = self.alive.get_mut(id.0) {
+            *alive = false;
+        }
+        Bundle {
+            kind: self.kinds.take(id),
+            position: self.positions.take(id),
+            renderable: self.renderables.take(id),
+            health: self.healths.take(id),
+            combat: self.combats.take(id),
+            actor: self.actors.take(id),
+            ai: self.ais.take(id),
+            inventory: self.inventories.take(id),
+            blocks_movement: self.blocks_movement.take(id).is_some(),
+            player: self.players.take(id).is_some(),
+        }
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.alive
+            .iter()
+            .enumerate()
+            .filter(|(_, &a)| a)
+            .map(|(i, _)| EntityId(i))
+    }
+
+    pub fn player(&self) -> Option<EntityId> {
+        self.players.iter().map(|(id, _)| id).next()
+    }
+
+    pub fn is_player(&self, id: EntityId) -> bool {
+        self.players.get(id).is_some()
+    }
+
+    pub fn name(&self, id: EntityId) -> &'static str {
+        match self.kinds.get(id) {
+            Some(kind) => &kind.def().name,
+            None => "thing",
+        }
+    }
+
+    pub fn color(&self, id: EntityId) -> Color {
+        self.renderables
+            .get(id)
+            .map_or(Color::rgb8(150, 150, 150), |r| r.fg_color)
+    }
+
+    /// Entity standing in the way on a cell
+    pub fn blocker_at(&self, x: i32, y: i32) -> Option<EntityId> {
+        self.blocks_movement
+            .iter()
+            .map(|(id, _)| id)
+            .find(|&id| self.positions.get(id) == Some(&Position { x, y }))
+    }
+
+    /// Attack with the bonus of the equipped items
+    pub fn attack_power(&self, id: EntityId) -> i32 {
+        let bonus = self
+            .inventories
+            .get(id)
+            .map_or(0, |i| i.equipped_bonus(ItemKind::Weapon));
+        self.combats.get(id).map_or(0, |c| c.attack) + bonus
+    }
+
+    /// Defence with the bonus of the equipped items
+    pub fn defence_power(&self, id: EntityId) -> i32 {
+        let bonus = self
+            .inventories
+            .get(id)
+            .map_or(0, |i| i.equipped_bonus(ItemKind::Armour));
+        self.combats.get(id).map_or(0, |c| c.defence) + bonus
+    }
+}
+
+fn flag(set: bool) -> Option<()> {
+    if set {
+        Some(())
+    } else {
+        None
+    }
+}
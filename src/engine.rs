@@ -1,9 +1,18 @@
-use crate::entity::{Entity, Tile};
-use std::f32::consts::PI;
+use crate::ecs::{EntityId, World};
+use crate::entity::Tile;
+#[cfg(test)]
+use crate::entity::TileType;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use tetra::graphics::Color;
-use tetra::math::Vec2;
 
-#[derive(Copy, Clone)]
+pub mod dijkstra;
+mod fov;
+mod path;
+pub use fov::{compute_fov, Fov, FovAlgorithm};
+pub use path::{path_finder, PathOptions, PathResult, PATH_BUDGET};
+
+#[derive(Copy, Clone, PartialEq)]
 pub enum Direction {
     None,
     North,
@@ -18,6 +27,22 @@ pub enum Direction {
 
 pub enum Action {
     Move,
+    Attack,
+    Wait,
+    Descend,
+    Ascend,
+    PickUp,
+    // Index of the item in the inventory
+    Drop(usize),
+    Use(usize),
+}
+
+/// The one random generator used for world generation and gameplay
+/// The same seed always gives the same game
+pub type GameRng = ChaCha8Rng;
+
+pub fn new_rng(seed: u64) -> GameRng {
+    GameRng::seed_from_u64(seed)
 }
 
 /// Take a geographical direction and return a movment tuple
@@ -56,11 +81,73 @@ pub fn orientation(dir: (i32, i32)) -> Direction {
     o
 }
 
+/// How the path finder deals with cells holding an entity
+#[derive(Copy, Clone)]
+pub enum Blocking {
+    Cost(i32),
+    Block,
+}
+
+/// Occupancy layer, one cell per tile
+/// Holds the entities which block movement
+pub struct Occupancy {
+    w: i32,
+    h: i32,
+    cells: Vec<Option<EntityId>>,
+}
+
+impl Occupancy {
+    pub fn new(w: i32, h: i32) -> Occupancy {
+        Occupancy {
+            w,
+            h,
+            cells: vec![None; (w * h) as usize],
+        }
+    }
+
+    /// Build the layer from the positions of the blocking entities
+    pub fn from_world(world: &World, w: i32, h: i32) -> Occupancy {
+        let mut occupancy = Occupancy::new(w, h);
+        for (id, _) in world.blocks_movement.iter() {
+            if let Some(p) = world.positions.get(id) {
+                occupancy.set(p.x, p.y, Some(id));
+            }
+        }
+        occupancy
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<EntityId> {
+        if is_in_map(x, y, self.w, self.h) {
+            self.cells[(y * self.w + x) as usize]
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, occupant: Option<EntityId>) {
+        if is_in_map(x, y, self.w, self.h) {
+            self.cells[(y * self.w + x) as usize] = occupant;
+        }
+    }
+
+    pub fn is_occupied(&self, x: i32, y: i32) -> bool {
+        self.get(x, y).is_some()
+    }
+
+    /// Keep the layer in sync with a moving entity
+    pub fn move_occupant(&mut self, from: (i32, i32), to: (i32, i32)) {
+        let occupant = self.get(from.0, from.1);
+        self.set(from.0, from.1, None);
+        self.set(to.0, to.1, occupant);
+    }
+}
+
 pub fn check_crossable_destination(
     player_x: i32,
     player_y: i32,
     dir: Direction,
     level_map: &Vec<Tile>,
+    occupancy: &Occupancy,
     w: i32,
     h: i32
 ) -> bool {
@@ -68,10 +155,12 @@ pub fn check_crossable_destination(
     let x = player_x + d.0;
     let y = player_y + d.1;
 
-    is_in_map(x, y, w, h) && level_map[(y * w + x) as usize].crossable
+    is_in_map(x, y, w, h)
+        && level_map[(y * w + x) as usize].crossable
+        && !occupancy.is_occupied(x, y)
 }
 
-fn is_in_map(x: i32, y: i32, w: i32, h: i32) -> bool {
+pub fn is_in_map(x: i32, y: i32, w: i32, h: i32) -> bool {
     if x>=0 && x < w && y>=0 && y < h {
         true
     } else {
@@ -79,176 +168,28 @@ fn is_in_map(x: i32, y: i32, w: i32, h: i32) -> bool {
     }
 }
 
-pub fn move_entity(entity: &mut Entity, dir: Direction) {
-    let delta = delta_pos(dir);
-    entity.x += delta.0;
-    entity.y += delta.1;
+/// Cell reached when moving from (x, y) toward dir
+pub fn destination(x: i32, y: i32, dir: Direction) -> (i32, i32) {
+    let d = delta_pos(dir);
+    (x + d.0, y + d.1)
 }
 
-/// Pathfinding with A* algorithm
-pub fn path_finder(
-    x_entity: i32,
-    y_entity: i32,
-    x_mouse: i32,
-    y_mouse: i32,
-    level_map: &Vec<Tile>,
-    w: i32,
-    h: i32,
-) -> Vec<Vec2<i32>> {
-    #[derive(Copy, Clone, Eq, PartialEq)]
-    struct Node {
-        id: i32,
-        x: i32,
-        y: i32,
-        f: i32,
-        g: i32,
-        parent: i32,
-    };
-
-    const MAX_CYCLE: i32 = 4000;
-    let mut path = Vec::new();
-
-    let mut open_list: Vec<Node> = Vec::new();
-    let mut closed_list: Vec<Node> = Vec::new();
-
-    // Add the starting point to the open list
-    open_list.push(Node {
-        x: x_entity,
-        y: y_entity,
-        f: 0,
-        g: 0,
-        id: 0,
-        parent: -1,
-    });
-
-    let mut id: i32 = 0;
-
-    // Check mouse position
-    if is_in_map(x_mouse, y_mouse, w, h)
-        && level_map[(x_mouse + y_mouse * w) as usize].crossable
-        && level_map[(x_mouse + y_mouse * w) as usize].visited
-    {
-        let mut cycle = 0;
-        while !open_list.is_empty() {
-            // Add a depth limit for path finding (to avoid infinite loop)
-            //if open_list.len() > (w * h) as usize {
-            cycle += 1;
-            if cycle > MAX_CYCLE {
-                closed_list.clear();
-                break;
-            }
-            let mut current_node = open_list[0];
-            let mut best_index: usize = 0;
-
-            // Choose the node with the best score
-            for i in 0..open_list.len() {
-                if open_list[i].f < current_node.f {
-                    current_node = open_list[i];
-                    best_index = i;
-                }
-            }
-            current_node.id = id;
-            closed_list.push(current_node);
-
-            // Update the node id value
-            id += 1;
-
-            // Remove the selected node from the open list
-            open_list.remove(best_index);
-
-            // If current is the goal, the break
-            if current_node.x == x_mouse && current_node.y == y_mouse {
-                break;
-            }
-
-            // create children list of cuurent node
-            let mut children: Vec<Vec2<i32>> = Vec::new();
-            for c in [
-                (1, 0),
-                (-1, 0),
-                (0, 1),
-                (0, -1),
-                (1, 1),
-                (1, -1),
-                (-1, 1),
-                (-1, -1),
-            ]
-            .iter()
-            {
-                // keep only node in the map and crossable
-                if c.0 + current_node.x >= 0
-                    && c.0 + current_node.x < w
-                    && c.1 + current_node.y >= 0
-                    && c.1 + current_node.y < h
-                    && level_map[(c.0 + current_node.x + (c.1 + current_node.y) * w) as usize]
-                        .crossable
-                    && level_map[(c.0 + current_node.x + (c.1 + current_node.y) * w) as usize]
-                        .visited
-                {
-                    children.push(Vec2::new(c.0 + current_node.x, c.1 + current_node.y));
-                }
-            }
-
-            for c in children.iter() {
-                // Zap node who are already in closed list
-                if closed_list.iter().any(|&n| n.x == c.x && n.y == c.y) {
-                    continue;
-                }
-                let g = current_node.g + 1;
-                let h = distance(c.x, c.y, x_mouse, y_mouse);
-                let f = g + h;
-
-                let mut out = false;
-
-                // Just keep the best node if it's already in open list
-                for op in open_list.iter() {
-                    if op.x == c.x && op.y == c.y && op.g < g {
-                        out = true;
-                    }
-                }
-                if out {
-                    continue;
-                }
-
-                open_list.push(Node {
-                    x: c.x,
-                    y: c.y,
-                    f: f,
-                    g: g,
-                    id: -1,
-                    parent: current_node.id,
-                });
-            }
-        }
+/// Melee attack, return the damage done to the defender
+pub fn attack(world: &mut World, attacker: EntityId, defender: EntityId, rng: &mut GameRng) -> i32 {
+    let power = world.attack_power(attacker).max(1);
+    let damage = (rng.gen_range(1..=power) - world.defence_power(defender)).max(0);
+    if let Some(health) = world.healths.get_mut(defender) {
+        health.hp -= damage;
     }
-
-    // Now, take the best way
-    // Go from child to parent until reach the starting point
-    if closed_list.len() != 0 {
-        let mut node = closed_list[closed_list.len() - 1];
-        path.push(Vec2::new(node.x, node.y));
-        while node.parent != -1 {
-            for o in closed_list.iter() {
-                if node.parent == o.id {
-                    path.push(Vec2::new(o.x, o.y));
-                    node = Node {
-                        id: o.id,
-                        x: o.x,
-                        y: o.y,
-                        f: o.f,
-                        g: o.g,
-                        parent: o.parent,
-                    };
-                }
-            }
-        }
-    }
-
-    path
+    damage
 }
 
-fn distance(x1: i32, y1: i32, x2: i32, y2: i32) -> i32 {
-    (x1 - x2).pow(2) + (y1 - y2).pow(2)
+pub fn move_entity(world: &mut World, id: EntityId, dir: Direction) {
+    let delta = delta_pos(dir);
+    if let Some(p) = world.positions.get_mut(id) {
+        p.x += delta.0;
+        p.y += delta.1;
+    }
 }
 
 /// Color modification for visited tile
@@ -261,61 +202,40 @@ pub fn visited_color(color: Color) -> (u8, u8, u8) {
     (r, g, b)
 }
 
-// Simple raycasting fov with range view
+/// Player's fov, update the visible and visited state of the map
+/// Only the tiles visible the previous time are reset
 pub fn fov(
     x_entity: i32,
     y_entity: i32,
     range: i32,
-    level_map: &mut Vec<Tile>,
+    level_map: &mut [Tile],
+    in_fov: &mut Fov,
+    algorithm: FovAlgorithm,
     w: i32,
-    h: i32,
-) -> Vec<[i32; 2]> {
-    // Create vec of tiles in fov
-    let mut in_fov_tile: Vec<[i32; 2]> = Vec::new();
-
-    // Player's tile allways visible
-    in_fov_tile.push([x_entity, y_entity]);
-
-    // Initialize all tile to unsee
-    for i in 0..w {
-        for j in 0..h {
-            level_map[(i + j * w) as usize].visible = false;
-        }
+) {
+    for &(x, y) in in_fov.cells() {
+        level_map[(x + y * w) as usize].visible = false;
     }
 
-    for a in 0..360 {
-        // Set normalize direction vector
-        let x = ((a as f32) * PI / 180.0).cos();
-        let y = ((a as f32) * PI / 180.0).sin();
-
-        // Player position (center)
-        let mut dx = (x_entity as f32) + 0.5;
-        let mut dy = (y_entity as f32) + 0.5;
-
-        for _i in 0..range {
-            // break if out of map
-            if dx >= w as f32 || dx < 0.0 || dy < 0.0 || dy >= h as f32 {
-                break;
-            }
-
-            // index af tile
-            let z = (dx.trunc() as i32) + (dy.trunc() as i32) * w;
-
-            level_map[z as usize].visible = true;
-            level_map[z as usize].visited = true;
-
-            // Add tile in fov
-            in_fov_tile.push([z % w, z / w]);
+    compute_fov(in_fov, x_entity, y_entity, range, level_map, algorithm);
+    for &(x, y) in in_fov.cells() {
+        let z = (x + y * w) as usize;
+        level_map[z].visible = true;
+        level_map[z].visited = true;
+    }
+}
 
-            if level_map[z as usize].see_through == false && z != x_entity + y_entity * w
-            // For the Door visual effect when Player is on a Door
-            {
-                break;
-            }
-            dx += x;
-            dy += y;
+/// Map drawn with `#` for walls and `.` for floor
+#[cfg(test)]
+pub fn map_from(rows: &[&str]) -> (Vec<Tile>, i32, i32) {
+    let (w, h) = (rows[0].len() as i32, rows.len() as i32);
+    let mut rng = new_rng(0);
+    let mut level_map = Vec::new();
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            let tile_type = if c == '#' { TileType::StoneWall } else { TileType::StoneFloor };
+            level_map.push(Tile::new(x as i32, y as i32, tile_type, &mut rng));
         }
     }
-
-    in_fov_tile
+    (level_map, w, h)
 }
@@ -0,0 +1,304 @@
+use crate::entity::Tile;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Cost of a straight and of a diagonal step, as for A*
+const STRAIGHT: i32 = 10;
+const DIAGONAL: i32 = 14;
+
+/// Value of the cells no goal can be reached from
+pub const UNREACHABLE: i32 = i32::MAX;
+
+// Fleeing distances are scaled by -1.2, so that running far away
+// is better than running into the nearest dead end
+const FLEE_NUM: i32 = -12;
+const FLEE_DEN: i32 = 10;
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+/// Distance field from a set of goals
+/// Going downhill from any cell leads to the nearest goal
+pub struct DijkstraMap {
+    w: i32,
+    h: i32,
+    // Only go through visited tiles (the player's memory)
+    known_only: bool,
+    values: Vec<i32>,
+    // Sorted, the map is only computed again when they change
+    goals: Vec<(i32, i32)>,
+    // Walkable cells as they were for the current values
+    walkable_cells: Vec<bool>,
+}
+
+impl DijkstraMap {
+    pub fn new(w: i32, h: i32, known_only: bool) -> DijkstraMap {
+        DijkstraMap {
+            w,
+            h,
+            known_only,
+            values: vec![UNREACHABLE; (w * h) as usize],
+            goals: Vec::new(),
+            walkable_cells: vec![false; (w * h) as usize],
+        }
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.w, self.h)
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> i32 {
+        if x >= 0 && x < self.w && y >= 0 && y < self.h {
+            self.values[(x + y * self.w) as usize]
+        } else {
+            UNREACHABLE
+        }
+    }
+
+    /// Change the goals, return false when the cached map was still good
+    /// Adding goals on an unchanged map only spreads from the new ones,
+    /// anything else computes it all again
+    pub fn set_goals(&mut self, level_map: &[Tile], goals: &[(i32, i32)]) -> bool {
+        let mut goals = goals.to_vec();
+        goals.sort_unstable();
+        goals.dedup();
+        if goals == self.goals {
+            return false;
+        }
+
+        let only_added = !self.goals.is_empty()
+            && self.goals.iter().all(|g| goals.binary_search(g).is_ok())
+            && self.same_walkable_cells(level_map);
+        if only_added {
+            let mut seeds = Vec::new();
+            for &(x, y) in goals.iter() {
+                if self.goals.binary_search(&(x, y)).is_err() && self.walkable(level_map, x, y) {
+                    let i = (x + y * self.w) as usize;
+                    self.values[i] = 0;
+                    seeds.push(i);
+                }
+            }
+            self.goals = goals;
+            self.relax(level_map, seeds);
+        } else {
+            self.goals = goals;
+            self.recompute(level_map);
+        }
+        true
+    }
+
+    /// Compute every distance from the goals
+    pub fn recompute(&mut self, level_map: &[Tile]) {
+        for v in self.values.iter_mut() {
+            *v = UNREACHABLE;
+        }
+        for i in 0..self.walkable_cells.len() {
+            self.walkable_cells[i] = self.walkable(level_map, i as i32 % self.w, i as i32 / self.w);
+        }
+        let mut seeds = Vec::new();
+        for &(x, y) in self.goals.iter() {
+            if self.walkable(level_map, x, y) {
+                let i = (x + y * self.w) as usize;
+                self.values[i] = 0;
+                seeds.push(i);
+            }
+        }
+        self.relax(level_map, seeds);
+    }
+
+    /// Update the map after some tiles changed (door, newly visited tile...)
+    pub fn refresh(&mut self, level_map: &[Tile], changed: &[(i32, i32)]) {
+        // A closed tile can make any distance grow, start again
+        if changed
+            .iter()
+            .any(|&(x, y)| !self.walkable(level_map, x, y) && self.get(x, y) != UNREACHABLE)
+        {
+            self.recompute(level_map);
+            return;
+        }
+
+        // An opened tile only makes distances shorter, spread from it
+        let mut seeds = Vec::new();
+        for &(x, y) in changed.iter() {
+            let open = self.walkable(level_map, x, y);
+            if x >= 0 && x < self.w && y >= 0 && y < self.h {
+                self.walkable_cells[(x + y * self.w) as usize] = open;
+            }
+            if !open {
+                continue;
+            }
+            let mut best = if self.goals.binary_search(&(x, y)).is_ok() {
+                0
+            } else {
+                UNREACHABLE
+            };
+            for &(dx, dy) in NEIGHBOURS.iter() {
+                let v = self.get(x + dx, y + dy);
+                if v != UNREACHABLE {
+                    best = best.min(v + step_cost(dx, dy));
+                }
+            }
+            let i = (x + y * self.w) as usize;
+            if best < self.values[i] {
+                self.values[i] = best;
+                seeds.push(i);
+            }
+        }
+        self.relax(level_map, seeds);
+    }
+
+    /// Map to flee from the goals, going downhill leads away from them
+    pub fn inverted(&self, level_map: &[Tile]) -> DijkstraMap {
+        let mut map = DijkstraMap::new(self.w, self.h, self.known_only);
+        let mut seeds = Vec::new();
+        for (i, &v) in self.values.iter().enumerate() {
+            if v != UNREACHABLE {
+                map.values[i] = v * FLEE_NUM / FLEE_DEN;
+                seeds.push(i);
+            }
+        }
+        map.relax(level_map, seeds);
+        map
+    }
+
+    /// Step toward the neighbour with the lowest value, among the free ones
+    pub fn downhill(&self, x: i32, y: i32, free: impl Fn(i32, i32) -> bool) -> Option<(i32, i32)> {
+        let mut best = None;
+        let mut best_value = self.get(x, y);
+        for &(dx, dy) in NEIGHBOURS.iter() {
+            let v = self.get(x + dx, y + dy);
+            if v < best_value && free(x + dx, y + dy) {
+                best = Some((dx, dy));
+                best_value = v;
+            }
+        }
+        best
+    }
+
+    fn same_walkable_cells(&self, level_map: &[Tile]) -> bool {
+        self.walkable_cells
+            .iter()
+            .enumerate()
+            .all(|(i, &w)| w == self.walkable(level_map, i as i32 % self.w, i as i32 / self.w))
+    }
+
+    fn walkable(&self, level_map: &[Tile], x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.w || y < 0 || y >= self.h {
+            return false;
+        }
+        let tile = &level_map[(x + y * self.w) as usize];
+        tile.crossable && (!self.known_only || tile.visited)
+    }
+
+    /// Spread the values of the seeds to the whole map
+    fn relax(&mut self, level_map: &[Tile], seeds: Vec<usize>) {
+        let mut open: BinaryHeap<Reverse<(i32, usize)>> =
+            seeds.into_iter().map(|i| Reverse((self.values[i], i))).collect();
+
+        while let Some(Reverse((value, i))) = open.pop() {
+            // Stale entry, the cell got better since
+            if value > self.values[i] {
+                continue;
+            }
+            let (x, y) = (i as i32 % self.w, i as i32 / self.w);
+            for &(dx, dy) in NEIGHBOURS.iter() {
+                let (nx, ny) = (x + dx, y + dy);
+                if !self.walkable(level_map, nx, ny) {
+                    continue;
+                }
+                let n = (nx + ny * self.w) as usize;
+                let v = value + step_cost(dx, dy);
+                if v < self.values[n] {
+                    self.values[n] = v;
+                    open.push(Reverse((v, n)));
+                }
+            }
+        }
+    }
+}
+
+fn step_cost(dx: i32, dy: i32) -> i32 {
+    if dx != 0 && dy != 0 {
+        DIAGONAL
+    } else {
+        STRAIGHT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::map_from;
+
+    const ROWS: [&str; 5] = [
+        "..........",
+        "....#.....",
+        "....#.....",
+        "....#.....",
+        "..........",
+    ];
+
+    #[test]
+    fn distances_from_a_goal() {
+        let (level_map, w, h) = map_from(&ROWS);
+        let mut map = DijkstraMap::new(w, h, false);
+        assert!(map.set_goals(&level_map, &[(2, 2)]));
+        assert_eq!(map.get(2, 2), 0);
+        assert_eq!(map.get(3, 2), STRAIGHT);
+        assert_eq!(map.get(3, 3), DIAGONAL);
+        // Around the wall, by four diagonal steps
+        assert_eq!(map.get(6, 2), 4 * DIAGONAL);
+        assert_eq!(map.get(4, 2), UNREACHABLE);
+        // Same goals, nothing to do
+        assert!(!map.set_goals(&level_map, &[(2, 2)]));
+    }
+
+    #[test]
+    fn added_goal_matches_a_full_rebuild() {
+        let (level_map, w, h) = map_from(&ROWS);
+        let mut map = DijkstraMap::new(w, h, false);
+        map.set_goals(&level_map, &[(2, 2)]);
+        map.set_goals(&level_map, &[(2, 2), (8, 3)]);
+        let mut full = DijkstraMap::new(w, h, false);
+        full.set_goals(&level_map, &[(8, 3), (2, 2)]);
+        assert!(map.values == full.values);
+    }
+
+    #[test]
+    fn added_goal_sees_the_opened_tiles() {
+        let (mut level_map, w, h) = map_from(&ROWS);
+        let mut map = DijkstraMap::new(w, h, false);
+        map.set_goals(&level_map, &[(2, 2)]);
+        // The wall is gone before the next goal comes
+        for y in 1..4 {
+            level_map[(4 + y * w) as usize].crossable = true;
+        }
+        map.set_goals(&level_map, &[(2, 2), (9, 0)]);
+        let mut full = DijkstraMap::new(w, h, false);
+        full.set_goals(&level_map, &[(2, 2), (9, 0)]);
+        assert!(map.values == full.values);
+        assert_eq!(map.get(4, 2), 2 * STRAIGHT);
+    }
+
+    #[test]
+    fn flee_map_is_scaled_by_minus_1_2() {
+        let (level_map, w, h) = map_from(&["......."]);
+        let mut map = DijkstraMap::new(w, h, false);
+        map.set_goals(&level_map, &[(0, 0)]);
+        let flee = map.inverted(&level_map);
+        // The far end is the lowest, then values rise a step at a time
+        assert_eq!(flee.get(6, 0), -72);
+        assert_eq!(flee.get(0, 0), -72 + 6 * STRAIGHT);
+        for x in 1..6 {
+            assert_eq!(flee.downhill(x, 0, |_, _| true), Some((1, 0)));
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use crate::entity::Tile;
+use std::f32::consts::PI;
+
+/// How the field of view is computed
+#[derive(Copy, Clone, PartialEq)]
+pub enum FovAlgorithm {
+    // 360 rays, cheap but with gaps at range
+    Raycast,
+    // Symmetric recursive shadowcasting
+    Shadowcast,
+}
+
+/// Set of the cells in view
+/// Lookup is O(1), and the list of cells allows to reset only those
+pub struct Fov {
+    w: i32,
+    h: i32,
+    visible: Vec<bool>,
+    cells: Vec<(i32, i32)>,
+}
+
+impl Fov {
+    pub fn new(w: i32, h: i32) -> Fov {
+        Fov {
+            w,
+            h,
+            visible: vec![false; (w * h) as usize],
+            cells: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && x < self.w && y >= 0 && y < self.h && self.visible[(x + y * self.w) as usize]
+    }
+
+    /// Cells in view, without duplicates
+    pub fn cells(&self) -> &[(i32, i32)] {
+        &self.cells
+    }
+
+    pub fn clear(&mut self) {
+        for &(x, y) in self.cells.iter() {
+            self.visible[(x + y * self.w) as usize] = false;
+        }
+        self.cells.clear();
+    }
+
+    fn insert(&mut self, x: i32, y: i32) {
+        if x >= 0 && x < self.w && y >= 0 && y < self.h && !self.visible[(x + y * self.w) as usize] {
+            self.visible[(x + y * self.w) as usize] = true;
+            self.cells.push((x, y));
+        }
+    }
+
+    fn is_blocking(&self, level_map: &[Tile], x: i32, y: i32) -> bool {
+        x < 0 || x >= self.w || y < 0 || y >= self.h || !level_map[(x + y * self.w) as usize].see_through
+    }
+}
+
+/// Fill `fov` with the cells seen from (x, y), the map is left untouched
+pub fn compute_fov(
+    fov: &mut Fov,
+    x: i32,
+    y: i32,
+    range: i32,
+    level_map: &[Tile],
+    algorithm: FovAlgorithm,
+) {
+    fov.clear();
+
+    // Entity's tile allways visible
+    fov.insert(x, y);
+
+    match algorithm {
+        FovAlgorithm::Raycast => raycast(fov, x, y, range, level_map),
+        FovAlgorithm::Shadowcast => {
+            for quadrant in 0..4 {
+                let mut caster = Caster {
+                    fov,
+                    level_map,
+                    origin: (x, y),
+                    quadrant,
+                    range,
+                };
+                caster.scan(1, Slope::new(-1, 1), Slope::new(1, 1));
+            }
+        }
+    }
+}
+
+// Simple raycasting fov with range view
+fn raycast(fov: &mut Fov, x_entity: i32, y_entity: i32, range: i32, level_map: &[Tile]) {
+    let (w, h) = (fov.w, fov.h);
+    for a in 0..360 {
+        // Set normalize direction vector
+        let x = ((a as f32) * PI / 180.0).cos();
+        let y = ((a as f32) * PI / 180.0).sin();
+
+        // Player position (center)
+        let mut dx = (x_entity as f32) + 0.5;
+        let mut dy = (y_entity as f32) + 0.5;
+
+        for _i in 0..range {
+            // break if out of map
+            if dx >= w as f32 || dx < 0.0 || dy < 0.0 || dy >= h as f32 {
+                break;
+            }
+
+            // index af tile
+            let z = (dx.trunc() as i32) + (dy.trunc() as i32) * w;
+
+            // Add tile in fov
+            fov.insert(z % w, z / w);
+
+            if !level_map[z as usize].see_through && z != x_entity + y_entity * w
+            // For the Door visual effect when Player is on a Door
+            {
+                break;
+            }
+            dx += x;
+            dy += y;
+        }
+    }
+}
+
+/// Exact slope of a row, as a fraction (den is always positive)
+#[derive(Copy, Clone)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+impl Slope {
+    fn new(num: i32, den: i32) -> Slope {
+        Slope { num, den }
+    }
+
+    /// Slope of the left edge of a tile
+    fn of_tile(depth: i32, col: i32) -> Slope {
+        Slope::new(2 * col - 1, 2 * depth)
+    }
+}
+
+/// Symmetric shadowcasting over one quadrant
+/// See https://www.albertford.com/shadowcasting/
+struct Caster<'a> {
+    fov: &'a mut Fov,
+    level_map: &'a [Tile],
+    origin: (i32, i32),
+    quadrant: i32,
+    range: i32,
+}
+
+impl<'a> Caster<'a> {
+    /// Map position of a (depth, col) tile of the quadrant
+    fn transform(&self, depth: i32, col: i32) -> (i32, i32) {
+        let (x, y) = self.origin;
+        match self.quadrant {
+            0 => (x + col, y - depth),
+            1 => (x + depth, y + col),
+            2 => (x + col, y + depth),
+            _ => (x - depth, y + col),
+        }
+    }
+
+    fn is_wall(&self, depth: i32, col: i32) -> bool {
+        let (x, y) = self.transform(depth, col);
+        self.fov.is_blocking(self.level_map, x, y)
+    }
+
+    fn reveal(&mut self, depth: i32, col: i32) {
+        if depth * depth + col * col <= self.range * self.range {
+            let (x, y) = self.transform(depth, col);
+            self.fov.insert(x, y);
+        }
+    }
+
+    fn scan(&mut self, depth: i32, mut start: Slope, end: Slope) {
+        if depth > self.range {
+            return;
+        }
+        // Round depth * slope, ties up for the start and down for the end
+        let min_col = (2 * depth * start.num + start.den).div_euclid(2 * start.den);
+        let max_col = -(-(2 * depth * end.num - end.den)).div_euclid(2 * end.den);
+
+        let mut prev_wall: Option<bool> = None;
+        for col in min_col..=max_col {
+            let wall = self.is_wall(depth, col);
+            let symmetric = col * start.den >= depth * start.num && col * end.den <= depth * end.num;
+            if wall || symmetric {
+                self.reveal(depth, col);
+            }
+            if prev_wall == Some(true) && !wall {
+                start = Slope::of_tile(depth, col);
+            }
+            if prev_wall == Some(false) && wall {
+                self.scan(depth + 1, start, Slope::of_tile(depth, col));
+            }
+            prev_wall = Some(wall);
+        }
+        if prev_wall == Some(false) {
+            self.scan(depth + 1, start, end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::map_from;
+
+    #[test]
+    fn shadowcast_is_symmetric() {
+        let (level_map, w, h) = map_from(&[
+            "#############",
+            "#...........#",
+            "#.#.#.#.#.#.#",
+            "#...........#",
+            "######.######",
+            "#...........#",
+            "#..#.....#..#",
+            "#.....#.....#",
+            "#############",
+        ]);
+        let floor: Vec<(i32, i32)> = level_map
+            .iter()
+            .filter(|t| t.see_through)
+            .map(|t| (t.x, t.y))
+            .collect();
+        let mut from_a = Fov::new(w, h);
+        let mut from_b = Fov::new(w, h);
+        for &(ax, ay) in floor.iter() {
+            compute_fov(&mut from_a, ax, ay, 8, &level_map, FovAlgorithm::Shadowcast);
+            for &(bx, by) in floor.iter() {
+                compute_fov(&mut from_b, bx, by, 8, &level_map, FovAlgorithm::Shadowcast);
+                assert_eq!(
+                    from_a.contains(bx, by),
+                    from_b.contains(ax, ay),
+                    "({}, {}) and ({}, {})",
+                    ax,
+                    ay,
+                    bx,
+                    by
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn raycast_and_shadowcast_agree_in_open_room() {
+        let mut rows = vec!["#####################"];
+        rows.extend(vec!["#...................#"; 19]);
+        rows.push("#####################");
+        let (level_map, w, h) = map_from(&rows);
+        let range = 8;
+        let mut raycast = Fov::new(w, h);
+        let mut shadowcast = Fov::new(w, h);
+        compute_fov(&mut raycast, 10, 10, range, &level_map, FovAlgorithm::Raycast);
+        compute_fov(&mut shadowcast, 10, 10, range, &level_map, FovAlgorithm::Shadowcast);
+        // Both draw a disc, they may only differ on its rim
+        for y in 0..h {
+            for x in 0..w {
+                let d = (x - 10).pow(2) + (y - 10).pow(2);
+                if d <= (range - 2) * (range - 2) {
+                    assert!(raycast.contains(x, y) && shadowcast.contains(x, y), "({}, {})", x, y);
+                } else if d > range * range {
+                    assert!(!raycast.contains(x, y) && !shadowcast.contains(x, y), "({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
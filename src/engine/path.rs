@@ -0,0 +1,207 @@
+use super::{Blocking, Occupancy};
+use crate::entity::Tile;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use tetra::math::Vec2;
+
+// Cost of a straight and of a diagonal step
+const STRAIGHT: i32 = 10;
+const DIAGONAL: i32 = 14;
+
+/// Nodes expanded before a search gives up
+pub const PATH_BUDGET: usize = 4000;
+
+/// What to avoid while looking for a path
+#[derive(Copy, Clone)]
+pub struct PathOptions {
+    /// What to do with occupied cells, the goal is always reachable
+    pub blocking: Blocking,
+    /// Stay on visited tiles (the player's memory)
+    pub known_only: bool,
+    /// Nodes expanded before giving up
+    pub budget: usize,
+}
+
+pub enum PathResult {
+    /// Steps from the cell next to the start up to the goal
+    Found(Vec<Vec2<i32>>),
+    /// The goal can't be reached
+    NoPath,
+    /// The search gave up before reaching the goal
+    BudgetExhausted,
+}
+
+/// Octile distance, never more than the real cost so A* stays optimal
+pub fn octile(x1: i32, y1: i32, x2: i32, y2: i32) -> i32 {
+    let dx = (x1 - x2).abs();
+    let dy = (y1 - y2).abs();
+    STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy)
+}
+
+/// Pathfinding with A* algorithm
+/// The open list is a binary heap, scores and parents are kept in grids
+pub fn path_finder(
+    from: (i32, i32),
+    to: (i32, i32),
+    level_map: &[Tile],
+    occupancy: &Occupancy,
+    options: PathOptions,
+    w: i32,
+    h: i32,
+) -> PathResult {
+    let walkable = |x: i32, y: i32| {
+        x >= 0
+            && x < w
+            && y >= 0
+            && y < h
+            && level_map[(x + y * w) as usize].crossable
+            && (!options.known_only || level_map[(x + y * w) as usize].visited)
+    };
+    if !walkable(to.0, to.1) {
+        return PathResult::NoPath;
+    }
+
+    let size = (w * h) as usize;
+    let index = |x: i32, y: i32| (x + y * w) as usize;
+    let mut g_score = vec![i32::MAX; size];
+    let mut parent = vec![usize::MAX; size];
+    let mut closed = vec![false; size];
+
+    // Ties on f are broken by the heuristic, to go straight at the goal
+    let mut open = BinaryHeap::new();
+    let start = index(from.0, from.1);
+    let goal = index(to.0, to.1);
+    g_score[start] = 0;
+    let start_h = octile(from.0, from.1, to.0, to.1);
+    open.push(Reverse((start_h, start_h, start)));
+
+    let mut expanded = 0;
+    while let Some(Reverse((_, _, current))) = open.pop() {
+        // Stale entry, a better one was already handled
+        if closed[current] {
+            continue;
+        }
+        if current == goal {
+            return PathResult::Found(rebuild(&parent, start, goal, w));
+        }
+        expanded += 1;
+        if expanded > options.budget {
+            return PathResult::BudgetExhausted;
+        }
+        closed[current] = true;
+
+        let (cx, cy) = (current as i32 % w, current as i32 / w);
+        for &(dx, dy) in [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ]
+        .iter()
+        {
+            let (x, y) = (cx + dx, cy + dy);
+            if !walkable(x, y) || closed[index(x, y)] {
+                continue;
+            }
+            let mut g = g_score[current] + if dx != 0 && dy != 0 { DIAGONAL } else { STRAIGHT };
+            if index(x, y) != goal && occupancy.is_occupied(x, y) {
+                match options.blocking {
+                    Blocking::Cost(cost) => g += cost * STRAIGHT,
+                    Blocking::Block => continue,
+                }
+            }
+            let i = index(x, y);
+            if g < g_score[i] {
+                g_score[i] = g;
+                parent[i] = current;
+                let h = octile(x, y, to.0, to.1);
+                open.push(Reverse((g + h, h, i)));
+            }
+        }
+    }
+
+    PathResult::NoPath
+}
+
+/// Go from the goal back to the start, then put the steps in walking order
+fn rebuild(parent: &[usize], start: usize, goal: usize, w: i32) -> Vec<Vec2<i32>> {
+    let mut path = Vec::new();
+    let mut node = goal;
+    while node != start {
+        path.push(Vec2::new(node as i32 % w, node as i32 / w));
+        node = parent[node];
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::map_from;
+
+    const OPTIONS: PathOptions = PathOptions {
+        blocking: Blocking::Block,
+        known_only: false,
+        budget: PATH_BUDGET,
+    };
+
+    /// Cost of the steps, starting at `from`
+    fn cost(from: (i32, i32), path: &[Vec2<i32>]) -> i32 {
+        let mut prev = Vec2::new(from.0, from.1);
+        let mut total = 0;
+        for step in path {
+            let (dx, dy) = ((step.x - prev.x).abs(), (step.y - prev.y).abs());
+            assert!(dx <= 1 && dy <= 1 && dx + dy > 0);
+            total += if dx + dy == 2 { DIAGONAL } else { STRAIGHT };
+            prev = *step;
+        }
+        total
+    }
+
+    fn find(rows: &[&str], from: (i32, i32), to: (i32, i32), options: PathOptions) -> PathResult {
+        let (level_map, w, h) = map_from(rows);
+        path_finder(from, to, &level_map, &Occupancy::new(w, h), options, w, h)
+    }
+
+    #[test]
+    fn open_path_costs_the_octile_distance() {
+        let rows = ["..........", "..........", "..........", "..........", ".........."];
+        match find(&rows, (1, 1), (8, 4), OPTIONS) {
+            PathResult::Found(path) => {
+                assert_eq!(path.last(), Some(&Vec2::new(8, 4)));
+                assert_eq!(cost((1, 1), &path), octile(1, 1, 8, 4));
+            }
+            _ => panic!("no path found"),
+        }
+    }
+
+    #[test]
+    fn path_around_a_wall_is_the_cheapest() {
+        let rows = ["..........", "....#.....", "....#.....", "....#.....", ".........."];
+        match find(&rows, (2, 2), (6, 2), OPTIONS) {
+            // Four diagonal steps, above or below the wall
+            PathResult::Found(path) => assert_eq!(cost((2, 2), &path), 4 * DIAGONAL),
+            _ => panic!("no path found"),
+        }
+    }
+
+    #[test]
+    fn walled_in_goal_has_no_path() {
+        let rows = ["..........", "......###.", "......#.#.", "......###.", ".........."];
+        assert!(matches!(find(&rows, (1, 1), (7, 2), OPTIONS), PathResult::NoPath));
+        // A wall is never a goal
+        assert!(matches!(find(&rows, (1, 1), (6, 2), OPTIONS), PathResult::NoPath));
+    }
+
+    #[test]
+    fn small_budget_gives_up() {
+        let rows = ["..........", "..........", "..........", "..........", ".........."];
+        let options = PathOptions { budget: 3, ..OPTIONS };
+        assert!(matches!(find(&rows, (0, 0), (9, 4), options), PathResult::BudgetExhausted));
+    }
+}
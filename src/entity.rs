@@ -1,78 +1,156 @@
+use crate::data::{self, EntityDef, ItemDef};
+use crate::engine::GameRng;
 use rand::prelude::*;
-use std::collections::HashMap;
 use tetra::graphics::Color;
 
-#[derive(Copy, Clone)]
-pub enum ItemType {
+#[derive(Copy, Clone, PartialEq)]
+pub enum TileType {
     None,
     StoneFloor,
     //WoodFloor,
     StoneWall,
     MudWall,
     //WoodWall,
+    Door,
+    StairsUp,
+    StairsDown,
 }
 
-#[derive(Copy, Clone)]
-pub enum EntityType {
-    Zombie,
-    Player,
-}
+impl TileType {
+    /// Name of the tile in the data and save files
+    pub fn code(self) -> &'static str {
+        match self {
+            TileType::None => "none",
+            TileType::StoneFloor => "stone_floor",
+            TileType::StoneWall => "stone_wall",
+            TileType::MudWall => "mud_wall",
+            TileType::Door => "door",
+            TileType::StairsUp => "stairs_up",
+            TileType::StairsDown => "stairs_down",
+        }
+    }
 
-pub enum DataValue {
-    Str(&'static str),
-    Int(i32),
+    pub fn from_code(code: &str) -> Option<TileType> {
+        let tile_type = match code {
+            "none" => TileType::None,
+            "stone_floor" => TileType::StoneFloor,
+            "stone_wall" => TileType::StoneWall,
+            "mud_wall" => TileType::MudWall,
+            "door" => TileType::Door,
+            "stairs_up" => TileType::StairsUp,
+            "stairs_down" => TileType::StairsDown,
+            _ => return None,
+        };
+        Some(tile_type)
+    }
 }
 
-pub enum DataField {
+/// What an item does when used
+#[derive(Copy, Clone, PartialEq)]
+pub enum ItemKind {
     Gold,
+    Potion,
+    Weapon,
+    Armour,
 }
 
-pub struct Entity {
-    pub entity: EntityType,
+impl ItemKind {
+    pub fn from_code(code: &str) -> Option<ItemKind> {
+        let kind = match code {
+            "gold" => ItemKind::Gold,
+            "potion" => ItemKind::Potion,
+            "weapon" => ItemKind::Weapon,
+            "armour" => ItemKind::Armour,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// Kind of item, an index in the item registry
+#[derive(Copy, Clone, PartialEq)]
+pub struct ItemType(pub usize);
+
+impl ItemType {
+    pub fn from_id(id: &str) -> Option<ItemType> {
+        data::registry().item_index(id).map(ItemType)
+    }
+
+    pub fn def(self) -> &'static ItemDef {
+        &data::registry().items[self.0]
+    }
+}
+
+/// Kind of entity, an index in the entity registry
+#[derive(Copy, Clone, PartialEq)]
+pub struct EntityType(pub usize);
+
+impl EntityType {
+    pub fn player() -> EntityType {
+        EntityType::from_id("player").expect("no player in the entity registry")
+    }
+
+    pub fn from_id(id: &str) -> Option<EntityType> {
+        data::registry().entity_index(id).map(EntityType)
+    }
+
+    pub fn def(self) -> &'static EntityDef {
+        &data::registry().entities[self.0]
+    }
+}
+
+#[derive(Clone)]
+pub struct Item {
+    pub item: ItemType,
     pub x: i32,
     pub y: i32,
-    pub glyph: u8,
+    pub glyph: char,
     pub fg_color: Color,
-    pub bg_color: Color,
-    pub data: HashMap<DataField, DataValue>,
+    // Amount of gold, 1 for the other items
+    pub quantity: i32,
+    pub equipped: bool,
+    // Already noticed by the player
+    pub seen: bool,
 }
 
-impl Entity {
-    pub fn new(x: i32, y: i32, e_type: EntityType) -> Entity {
-
-        let mut e = Entity {
-            entity: e_type,
-            x: x,
-            y: y,
-            glyph: 0,
-            fg_color: Color::rgba8(150, 150, 150, 255),
-            bg_color: Color::rgba8(0, 0, 0, 0),
-            data: HashMap::new(),
-        };
-
-        match e_type {
-            EntityType::Player => e.create_player(),
-            EntityType::Zombie => e.create_zombie(),
+impl Item {
+    pub fn new(x: i32, y: i32, i_type: ItemType, rng: &mut GameRng) -> Item {
+        let def = i_type.def();
+        Item {
+            item: i_type,
+            x,
+            y,
+            glyph: def.glyph,
+            fg_color: def.color,
+            quantity: rng.gen_range(def.quantity.0..=def.quantity.1),
+            equipped: false,
+            seen: false,
         }
+    }
 
-        e
+    pub fn name(&self) -> &'static str {
+        &self.item.def().name
     }
 
-    fn create_player(&mut self) {
-        self.glyph = '@' as u8;
+    pub fn kind(&self) -> ItemKind {
+        self.item.def().kind
     }
 
-    fn create_zombie(&mut self) {
-        self.glyph = 'Z' as u8;
-        self.fg_color = Color::rgb8(150, 100, 80);
+    /// Hp healed by a potion, attack or defence given by an equipment
+    pub fn bonus(&self) -> i32 {
+        self.item.def().bonus
+    }
+
+    pub fn is_equipment(&self) -> bool {
+        matches!(self.kind(), ItemKind::Weapon | ItemKind::Armour)
     }
 }
 
 pub struct Tile {
-    pub item: ItemType,
+    pub tile_type: TileType,
     pub x: i32,
     pub y: i32,
-    pub glyph: u8,
+    pub glyph: char,
     pub fg_color: Color,
     pub bg_color: Color,
     pub visible: bool,
@@ -82,57 +160,27 @@ pub struct Tile {
 }
 
 impl Tile {
-    pub fn new(x: i32, y: i32, style: ItemType) -> Tile {
+    pub fn new(x: i32, y: i32, style: TileType, rng: &mut GameRng) -> Tile {
         let mut tile = Tile {
-            item: style,
-            x: x,
-            y: y,
-            glyph: 0,
+            tile_type: style,
+            x,
+            y,
+            glyph: '\0',
             fg_color: Color::rgb8(150, 100, 150),
             bg_color: Color::rgb8(0, 0, 0),
-            visible: true,
+            visible: false,
             see_through: true,
             crossable: true,
             visited: false,
         };
 
-        match tile.item {
-            ItemType::StoneFloor => tile.stone_floor(),
-            ItemType::StoneWall => tile.stone_wall(),
-            ItemType::MudWall => tile.mud_wall(),
-            _ => {}
+        if let Some(def) = data::registry().tile(style) {
+            tile.glyph = def.glyph;
+            tile.fg_color = def.fg.pick(rng);
+            tile.bg_color = def.bg.pick(rng);
+            tile.crossable = def.crossable;
+            tile.see_through = def.see_through;
         }
         tile
     }
-
-    fn stone_floor(&mut self) {
-        let mut rng = rand::thread_rng();
-        let gray: u8 = rng.gen_range(60..100);
-        self.fg_color = Color::rgb8(gray, gray, gray);
-        self.glyph = '.' as u8;
-    }
-
-    fn mud_wall(&mut self) {
-        let mut rng = rand::thread_rng();
-        let rb: u8 = rng.gen_range(100..120);
-        let gb: u8 = rng.gen_range(80..90);
-        let bb: u8 = rng.gen_range(58..65);
-        let black: u8 = rng.gen_range(5..20);
-        self.glyph = '#' as u8;
-        self.bg_color = Color::rgb8(rb, gb, bb);
-        self.fg_color = Color::rgb8(black, black, black);
-        self.crossable = false;
-        self.see_through = false;
-    }
-
-    fn stone_wall(&mut self) {
-        let mut rng = rand::thread_rng();
-        let gray: u8 = rng.gen_range(100..150);
-        let black: u8 = rng.gen_range(5..20);
-        self.glyph = '#' as u8;
-        self.bg_color = Color::rgb8(gray, gray, gray);
-        self.fg_color = Color::rgb8(black, black, black);
-        self.crossable = false;
-        self.see_through = false;
-    }
 }
@@ -1,75 +1,109 @@
-use tetra::graphics::{self, Color, DrawParams, Rectangle, Texture};
-use tetra::math::Vec2;
-use tetra::Context;
+use tetra::graphics::Color;
+
+mod ansi;
+pub mod cp437;
+mod font;
+mod headless;
+pub mod tileset;
+mod window;
+pub use ansi::Ansi;
+pub use font::UiFont;
+pub use headless::Headless;
+pub use tileset::Tileset;
+pub use window::Window;
 
 const MAX_LAYER: usize = 3;
 
-struct Cell {
-    x: i32,
-    y: i32,
-    glyph: u8,
-    fg_color: Color,
-    bg_color: Color,
+#[derive(Copy, Clone, PartialEq)]
+/// A glyph and its colours, `\0` for an empty cell
+pub struct Cell {
+    pub glyph: char,
+    pub fg_color: Color,
+    pub bg_color: Color,
+}
+
+/// Lines of text over a rectangle of cells, drawn by a backend with a font
+#[derive(Clone, PartialEq)]
+pub struct TextBox {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    // Each line is made of coloured parts, it is wrapped by the backend
+    pub lines: Vec<Vec<(String, Color)>>,
+    // Keep the last lines when they don't all fit
+    pub from_bottom: bool,
+}
+
+/// Where the terminal is drawn
+pub trait Backend {
+    /// What the backend draws into, the tetra context for a window
+    type Target: ?Sized;
+
+    /// Show the current content of the terminal
+    fn present(&mut self, target: &mut Self::Target, terminal: &Terminal);
 }
 
+/// Layers of cells, drawn by a backend
 pub struct Terminal {
     w: i32,
     h: i32,
-    cell_w: i32,
-    cell_h: i32,
     current_layer: usize,
     fg_color: Color,
     bg_color: Color,
-    tileset: Texture,
     console: Vec<Vec<Cell>>,
+    text_boxes: Vec<TextBox>,
+    // Rectangle put and print draw in, x, y, w, h
+    clip: (i32, i32, i32, i32),
 }
 
 impl Terminal {
-    pub fn new(ctx: &mut Context, w: i32, h: i32, cell_w: i32, cell_h: i32) -> Terminal {
-        let ts: Texture;
-        match cell_w {
-            24 => ts = Texture::new(ctx, "./assets/24x24.png").unwrap(),
-            16 => ts = Texture::new(ctx, "./assets/16x16.png").unwrap(),
-            8 => ts = Texture::new(ctx, "./assets/8x8.png").unwrap(),
-            _ => ts = Texture::new(ctx, "./assets/24x24.png").unwrap(),
-        }
-        let bg = Color::rgb8(0, 0, 0);
-        let fg = Color::rgb8(255, 255, 255);
-        let mut cons = Vec::new();
-        for l in 0..MAX_LAYER {
-            let mut layer = Vec::new();
-            for i in 0..(w * h) as usize {
-                layer.push(Cell {
-                    x: (i as i32) % w,
-                    y: (i as i32) / w,
-                    glyph: 0,
-                    fg_color: fg,
-                    bg_color: bg,
-                });
-            }
-            cons.push(layer);
-        }
+    pub fn new(w: i32, h: i32) -> Terminal {
+        let cell = Cell {
+            glyph: '\0',
+            fg_color: Color::rgb8(255, 255, 255),
+            bg_color: Color::rgb8(0, 0, 0),
+        };
         Terminal {
-            w: w,
-            h: h,
-            cell_w: cell_w,
-            cell_h: cell_h,
+            w,
+            h,
             current_layer: 0,
             fg_color: Color::rgb8(200, 200, 200),
             bg_color: Color::rgb8(0, 0, 0),
-            tileset: ts,
-            console: cons,
+            console: vec![vec![cell; (w * h) as usize]; MAX_LAYER],
+            text_boxes: Vec::new(),
+            clip: (0, 0, w, h),
         }
     }
-    pub fn clear(&mut self, ctx: &mut Context) {
-        graphics::clear(ctx, self.bg_color);
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.w, self.h)
+    }
+
+    /// Cells of each layer, from the bottom one, row by row
+    pub fn layers(&self) -> impl Iterator<Item = &[Cell]> {
+        self.console.iter().map(|l| l.as_slice())
+    }
+
+    pub fn clear(&mut self) {
         for layer in self.console.iter_mut() {
             for cell in layer.iter_mut() {
-                cell.glyph = 0;
+                cell.glyph = '\0';
                 cell.bg_color = self.bg_color;
                 cell.fg_color = self.fg_color;
             }
         }
+        self.text_boxes.clear();
+    }
+
+    /// Text boxes of the frame, in the order they were added
+    pub fn text_boxes(&self) -> &[TextBox] {
+        &self.text_boxes
+    }
+
+    /// Text for a backend with a font, nothing is printed in the cells
+    pub fn text_box(&mut self, text_box: TextBox) {
+        self.text_boxes.push(text_box);
     }
 
     pub fn layer(&mut self, l: usize) {
@@ -84,7 +118,7 @@ impl Terminal {
         self.bg_color = c;
     }
 
-    pub fn pick(&self, x: i32, y: i32) -> u8 {
+    pub fn pick(&self, x: i32, y: i32) -> char {
         let index = y * self.w + x;
         self.console[self.current_layer][index as usize].glyph
     }
@@ -97,7 +131,25 @@ impl Terminal {
         self.console[self.current_layer][index as usize].fg_color
     }
 
-    pub fn put(&mut self, x: i32, y: i32, glyph: u8) {
+    /// Only draw in a rectangle of the terminal until unclip
+    pub fn clip(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = (x + w).min(self.w);
+        let bottom = (y + h).min(self.h);
+        self.clip = (left, top, right - left, bottom - top);
+    }
+
+    pub fn unclip(&mut self) {
+        self.clip = (0, 0, self.w, self.h);
+    }
+
+    /// Cells outside the terminal or the clip rectangle are ignored
+    pub fn put(&mut self, x: i32, y: i32, glyph: char) {
+        let (cx, cy, cw, ch) = self.clip;
+        if x < cx || x >= cx + cw || y < cy || y >= cy + ch {
+            return;
+        }
         let index = y * self.w + x;
         self.console[self.current_layer][index as usize].glyph = glyph;
         self.console[self.current_layer][index as usize].fg_color = self.fg_color;
@@ -106,50 +158,7 @@ impl Terminal {
 
     pub fn print(&mut self, x: i32, y: i32, string: String) {
         for (i, letter) in string.chars().enumerate() {
-            self.put(x + i as i32, y, letter as u8);
-        }
-    }
-
-    pub fn refresh(&mut self, ctx: &mut Context) {
-        for (n, layer) in self.console.iter_mut().enumerate() {
-            for cell in layer.iter_mut() {
-                let tx = cell.glyph as i32 % 16;
-                let ty = cell.glyph as i32 / 16;
-                let bx = 219 % 16;
-                let by = 219 / 16;
-                if n == 0 {
-                    self.tileset.draw_region(
-                        ctx,
-                        Rectangle::new(
-                            (self.cell_w * bx) as f32,
-                            (self.cell_h * by) as f32,
-                            self.cell_w as f32,
-                            self.cell_h as f32,
-                        ),
-                        DrawParams::new()
-                            .position(Vec2::new(
-                                (cell.x * self.cell_w) as f32,
-                                (cell.y * self.cell_h) as f32,
-                            ))
-                            .color(cell.bg_color),
-                    );
-                }
-                self.tileset.draw_region(
-                    ctx,
-                    Rectangle::new(
-                        (self.cell_w * tx) as f32,
-                        (self.cell_h * ty) as f32,
-                        self.cell_w as f32,
-                        self.cell_h as f32,
-                    ),
-                    DrawParams::new()
-                        .position(Vec2::new(
-                            (cell.x * self.cell_w) as f32,
-                            (cell.y * self.cell_h) as f32,
-                        ))
-                        .color(cell.fg_color),
-                );
-            }
+            self.put(x + i as i32, y, letter);
         }
     }
 }
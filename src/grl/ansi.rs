@@ -0,0 +1,59 @@
+use super::headless::{rgb, to_char};
+use super::{Backend, Cell, Headless, Terminal};
+use std::fmt::Write;
+
+/// Draw the terminal in a text terminal, with truecolor ANSI escapes
+/// Only the cells changed since the last frame are written
+pub struct Ansi {
+    screen: Headless,
+    // What the text terminal shows, None until first written
+    shown: Vec<Option<Cell>>,
+}
+
+impl Ansi {
+    pub fn new(w: i32, h: i32) -> Ansi {
+        Ansi {
+            screen: Headless::new(w, h),
+            shown: vec![None; (w * h) as usize],
+        }
+    }
+}
+
+impl Backend for Ansi {
+    /// Escapes to send to the text terminal
+    type Target = String;
+
+    fn present(&mut self, out: &mut String, terminal: &Terminal) {
+        self.screen.present(&mut (), terminal);
+        let (w, h) = terminal.size();
+        // Colours of the last cell written, kept when they don't change
+        let mut colors = None;
+        for y in 0..h {
+            // Cursor position, None when it must be moved
+            let mut next_x = None;
+            for x in 0..w {
+                let cell = self.screen.cell(x, y);
+                let shown = &mut self.shown[(y * w + x) as usize];
+                if *shown == Some(cell) {
+                    next_x = None;
+                    continue;
+                }
+                *shown = Some(cell);
+                if next_x != Some(x) {
+                    let _ = write!(out, "\x1b[{};{}H", y + 1, x + 1);
+                }
+                let (fg, bg) = (rgb(cell.fg_color), rgb(cell.bg_color));
+                if colors != Some((fg, bg)) {
+                    let _ = write!(
+                        out,
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                        fg.0, fg.1, fg.2, bg.0, bg.1, bg.2
+                    );
+                    colors = Some((fg, bg));
+                }
+                out.push(to_char(cell.glyph));
+                next_x = Some(x + 1);
+            }
+        }
+    }
+}
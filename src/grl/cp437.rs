@@ -0,0 +1,48 @@
+/// Unicode character of each code of a CP437 tileset
+const CP437: [char; 256] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// Tiles past the CP437 ones are reached through the private use area
+const PRIVATE_USE: u32 = 0xE000;
+
+/// Tile of the tileset drawing a glyph
+/// CP437 characters keep their code, `U+E000 + n` is the tile n
+pub fn to_tile(glyph: char) -> Option<u32> {
+    let code = glyph as u32;
+    if (0x20..0x7F).contains(&code) {
+        return Some(code);
+    }
+    if (PRIVATE_USE..=0xF8FF).contains(&code) {
+        return Some(code - PRIVATE_USE);
+    }
+    CP437.iter().position(|&c| c == glyph).map(|i| i as u32)
+}
+
+/// Glyph drawn with the tile n
+pub fn from_tile(tile: u32) -> char {
+    match CP437.get(tile as usize) {
+        Some(&c) => c,
+        None => std::char::from_u32(PRIVATE_USE + tile).unwrap_or('?'),
+    }
+}
+
+/// True for the glyphs which only exist as tiles, with no character to print
+pub fn is_private(glyph: char) -> bool {
+    (PRIVATE_USE..=0xF8FF).contains(&(glyph as u32))
+}
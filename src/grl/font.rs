@@ -0,0 +1,182 @@
+use super::TextBox;
+use tetra::graphics::text::{Font, Text};
+use tetra::graphics::{Color, DrawParams};
+use tetra::math::Vec2;
+use tetra::Context;
+
+/// Part of a line, ready to draw
+struct Label {
+    text: Text,
+    position: Vec2<f32>,
+    color: Color,
+}
+
+/// TrueType font for the text boxes, at its own size whatever the tileset
+/// Lines are wrapped on the width of their words, not on a number of cells
+pub struct UiFont {
+    font: Font,
+    // Only used to measure strings
+    ruler: Text,
+    line_height: f32,
+    // Right side of the mark put after a string to measure it
+    mark_right: f32,
+    // Boxes of the last frame, laid out again only when they change
+    laid_out: Vec<(TextBox, Vec<Label>)>,
+}
+
+impl UiFont {
+    pub fn load(ctx: &mut Context, path: &str, size: f32) -> tetra::Result<UiFont> {
+        let font = Font::vector(ctx, path, size)?;
+        let mut ruler = Text::new("|", font.clone());
+        let bottom = |ruler: &mut Text, ctx: &mut Context| ruler.get_bounds(ctx).map_or(0.0, |b| b.bottom());
+        let mark_right = ruler.get_bounds(ctx).map_or(0.0, |b| b.right());
+        let one_line = bottom(&mut ruler, ctx);
+        ruler.set_content("|\n|");
+        let line_height = bottom(&mut ruler, ctx) - one_line;
+        Ok(UiFont {
+            font,
+            ruler,
+            line_height,
+            mark_right,
+            laid_out: Vec::new(),
+        })
+    }
+
+    /// Forget the layouts, the cells changed size
+    pub fn reset(&mut self) {
+        self.laid_out.clear();
+    }
+
+    /// Draw the text boxes, with cells of the given size
+    pub fn draw(&mut self, ctx: &mut Context, text_boxes: &[TextBox], cell_size: (i32, i32)) {
+        self.laid_out.truncate(text_boxes.len());
+        for (i, text_box) in text_boxes.iter().enumerate() {
+            if self.laid_out.get(i).map(|(b, _)| b) != Some(text_box) {
+                let labels = self.lay_out(ctx, text_box, cell_size);
+                if i < self.laid_out.len() {
+                    self.laid_out[i] = (text_box.clone(), labels);
+                } else {
+                    self.laid_out.push((text_box.clone(), labels));
+                }
+            }
+            for label in self.laid_out[i].1.iter_mut() {
+                label
+                    .text
+                    .draw(ctx, DrawParams::new().position(label.position).color(label.color));
+            }
+        }
+    }
+
+    /// Wrap the lines of a box and keep the rows which fit in it
+    fn lay_out(&mut self, ctx: &mut Context, text_box: &TextBox, (cell_w, cell_h): (i32, i32)) -> Vec<Label> {
+        let width = (text_box.w * cell_w) as f32;
+        let fit = ((text_box.h * cell_h) as f32 / self.line_height) as usize;
+        let mut rows = Vec::new();
+        if text_box.from_bottom {
+            for line in text_box.lines.iter().rev() {
+                let mut wrapped = self.wrap(ctx, line, width);
+                wrapped.append(&mut rows);
+                rows = wrapped;
+                if rows.len() >= fit {
+                    break;
+                }
+            }
+            rows.drain(..rows.len().saturating_sub(fit));
+        } else {
+            for line in text_box.lines.iter() {
+                rows.append(&mut self.wrap(ctx, line, width));
+                if rows.len() >= fit {
+                    break;
+                }
+            }
+            rows.truncate(fit);
+        }
+
+        let origin = Vec2::new((text_box.x * cell_w) as f32, (text_box.y * cell_h) as f32);
+        let mut labels = Vec::new();
+        for (row, parts) in rows.into_iter().enumerate() {
+            for (text, color, x) in parts {
+                labels.push(Label {
+                    text: Text::new(text, self.font.clone()),
+                    position: origin + Vec2::new(x, row as f32 * self.line_height),
+                    color,
+                });
+            }
+        }
+        labels
+    }
+
+    /// Cut a line into rows no wider than `width`, broken on spaces when possible
+    /// Each part of a row comes with its offset from the left
+    fn wrap(&mut self, ctx: &mut Context, line: &[(String, Color)], width: f32) -> Vec<Vec<(String, Color, f32)>> {
+        let chars: Vec<(char, Color)> = line
+            .iter()
+            .flat_map(|(text, color)| text.chars().map(move |c| (c, *color)))
+            .collect();
+
+        let mut rows = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            // Add words while they fit
+            let mut end = next_word_end(&chars, start);
+            while end < chars.len() {
+                let next = next_word_end(&chars, end);
+                if self.advance(ctx, &chars[start..next]) > width {
+                    break;
+                }
+                end = next;
+            }
+            // A word longer than the row is cut
+            while end > start + 1 && self.advance(ctx, &chars[start..end]) > width {
+                end -= 1;
+            }
+            rows.push(self.place(ctx, &chars[start..end]));
+
+            // No space at the beginning of a row
+            start = end;
+            while start < chars.len() && chars[start].0 == ' ' {
+                start += 1;
+            }
+        }
+        rows
+    }
+
+    /// Group a row by colour, with the offset of each part
+    fn place(&mut self, ctx: &mut Context, chars: &[(char, Color)]) -> Vec<(String, Color, f32)> {
+        let mut parts: Vec<(String, Color, f32)> = Vec::new();
+        for (i, &(c, color)) in chars.iter().enumerate() {
+            match parts.last_mut() {
+                Some(part) if part.1 == color => part.0.push(c),
+                _ => {
+                    let x = self.advance(ctx, &chars[..i]);
+                    parts.push((c.to_string(), color, x));
+                }
+            }
+        }
+        parts
+    }
+
+    /// Width of a string once drawn, trailing spaces included
+    fn advance(&mut self, ctx: &mut Context, chars: &[(char, Color)]) -> f32 {
+        if chars.is_empty() {
+            return 0.0;
+        }
+        // The bounds only cover the visible pixels, so a mark is put at the end
+        let mut content: String = chars.iter().map(|c| c.0).collect();
+        content.push('|');
+        self.ruler.set_content(content);
+        self.ruler.get_bounds(ctx).map_or(0.0, |b| b.right()) - self.mark_right
+    }
+}
+
+/// End of the word starting at `from`, the spaces before it included
+fn next_word_end(chars: &[(char, Color)], from: usize) -> usize {
+    let mut i = from;
+    while i < chars.len() && chars[i].0 == ' ' {
+        i += 1;
+    }
+    while i < chars.len() && chars[i].0 != ' ' {
+        i += 1;
+    }
+    i
+}
@@ -0,0 +1,165 @@
+use super::{cp437, Backend, Cell, Terminal};
+use std::fmt::Write;
+use tetra::graphics::Color;
+
+/// Draw the terminal in memory, no window needed
+/// The screen can be checked cell by cell, or dumped as text
+pub struct Headless {
+    w: i32,
+    h: i32,
+    screen: Vec<Cell>,
+}
+
+impl Headless {
+    pub fn new(w: i32, h: i32) -> Headless {
+        let cell = Cell {
+            glyph: '\0',
+            fg_color: Color::rgb8(255, 255, 255),
+            bg_color: Color::rgb8(0, 0, 0),
+        };
+        Headless {
+            w,
+            h,
+            screen: vec![cell; (w * h) as usize],
+        }
+    }
+
+    /// What is seen on a cell once the layers are stacked
+    pub fn cell(&self, x: i32, y: i32) -> Cell {
+        self.screen[(y * self.w + x) as usize]
+    }
+
+    /// One line per row, without colours
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for y in 0..self.h {
+            for x in 0..self.w {
+                text.push(to_char(self.cell(x, y).glyph));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Like `text`, with truecolor ANSI escapes
+    pub fn ansi(&self) -> String {
+        let mut text = String::new();
+        for y in 0..self.h {
+            for x in 0..self.w {
+                let cell = self.cell(x, y);
+                let (fg, bg) = (rgb(cell.fg_color), rgb(cell.bg_color));
+                let _ = write!(
+                    text,
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m{}",
+                    fg.0,
+                    fg.1,
+                    fg.2,
+                    bg.0,
+                    bg.1,
+                    bg.2,
+                    to_char(cell.glyph)
+                );
+            }
+            text.push_str("\x1b[0m\n");
+        }
+        text
+    }
+}
+
+impl Backend for Headless {
+    type Target = ();
+
+    /// Stack the layers as the window does
+    /// A translucent glyph only tints the background, as the mouse overlay does
+    fn present(&mut self, _: &mut (), terminal: &Terminal) {
+        let mut layers = terminal.layers();
+        if let Some(bottom) = layers.next() {
+            self.screen.copy_from_slice(bottom);
+        }
+        for layer in layers {
+            for (screen, cell) in self.screen.iter_mut().zip(layer.iter()) {
+                if cell.glyph == '\0' {
+                    continue;
+                }
+                if cell.fg_color.a < 1.0 {
+                    screen.bg_color = blend(screen.bg_color, cell.fg_color);
+                } else {
+                    screen.glyph = cell.glyph;
+                    screen.fg_color = cell.fg_color;
+                }
+            }
+        }
+    }
+}
+
+/// Character to print for a glyph, tiles without one are shown as `?`
+pub fn to_char(glyph: char) -> char {
+    match glyph {
+        '\0' => ' ',
+        c if cp437::is_private(c) || c.is_control() => '?',
+        c => c,
+    }
+}
+
+pub fn rgb(c: Color) -> (u8, u8, u8) {
+    let to_u8 = |v: f32| (v * 255.0).round() as u8;
+    (to_u8(c.r), to_u8(c.g), to_u8(c.b))
+}
+
+fn blend(under: Color, over: Color) -> Color {
+    let mix = |u: f32, o: f32| u + (o - u) * over.a;
+    Color::rgb(mix(under.r, over.r), mix(under.g, over.g), mix(under.b, over.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upper_glyph_over_lower_background() {
+        let red = Color::rgb8(255, 0, 0);
+        let mut terminal = Terminal::new(2, 1);
+        terminal.layer(0);
+        terminal.bg_color(red);
+        terminal.put(0, 0, '.');
+        terminal.put(1, 0, '.');
+        terminal.layer(1);
+        terminal.bg_color(Color::rgb8(0, 0, 255));
+        terminal.fg_color(Color::rgb8(255, 255, 255));
+        terminal.put(0, 0, '@');
+
+        let mut screen = Headless::new(2, 1);
+        screen.present(&mut (), &terminal);
+        assert_eq!(screen.text(), "@.\n");
+        assert!(screen.cell(0, 0).bg_color == red);
+        assert!(screen.cell(0, 0).fg_color == Color::rgb8(255, 255, 255));
+    }
+
+    #[test]
+    fn print_stops_at_the_clip() {
+        let mut terminal = Terminal::new(4, 1);
+        terminal.clip(0, 0, 2, 1);
+        terminal.print(0, 0, "abcd".to_string());
+        terminal.unclip();
+        terminal.put(3, 0, '@');
+
+        let mut screen = Headless::new(4, 1);
+        screen.present(&mut (), &terminal);
+        assert_eq!(screen.text(), "ab @\n");
+    }
+
+    #[test]
+    fn translucent_glyph_tints_the_background() {
+        let mut terminal = Terminal::new(1, 1);
+        terminal.layer(0);
+        terminal.put(0, 0, '.');
+        terminal.layer(1);
+        terminal.fg_color(Color::rgba8(255, 255, 0, 128));
+        terminal.put(0, 0, '█');
+
+        let mut screen = Headless::new(1, 1);
+        screen.present(&mut (), &terminal);
+        assert_eq!(screen.cell(0, 0).glyph, '.');
+        assert_eq!(rgb(screen.cell(0, 0).bg_color), (128, 128, 0));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::config::{self, Section};
+use std::io;
+
+pub const TILESETS_PATH: &str = "./data/tilesets.cfg";
+
+// Built-in copy of the file, used when it can't be read
+const TILESETS: &str = include_str!("../../data/tilesets.cfg");
+
+/// A bitmap tileset and how its glyphs are laid out
+#[derive(Clone)]
+pub struct Tileset {
+    pub name: String,
+    pub path: String,
+    pub cell_w: i32,
+    pub cell_h: i32,
+    // Tiles in a row of the image, None to fill the image width
+    pub columns: Option<u32>,
+    // CP437 code of the first tile
+    pub first: u32,
+    // Pixels of this colour are made transparent
+    pub key: Option<(u8, u8, u8)>,
+}
+
+/// Tilesets of the file, the built-in list is used if it is broken
+pub fn load(path: &str) -> Vec<Tileset> {
+    read(path).unwrap_or_else(|e| {
+        eprintln!("Can't load the tilesets: {}", e);
+        from_sections(&config::parse(TILESETS).expect("bad built-in tilesets"))
+            .expect("bad built-in tilesets")
+    })
+}
+
+fn read(path: &str) -> io::Result<Vec<Tileset>> {
+    from_sections(&config::read(path)?).map_err(|e| config::invalid(path, &e))
+}
+
+fn from_sections(sections: &[Section]) -> Result<Vec<Tileset>, String> {
+    let mut tilesets = Vec::new();
+    for s in sections.iter() {
+        let bad = |key: &str, v: &str| format!("[{}] bad {} `{}`", s.name, key, v);
+        let path = s.get("path").ok_or_else(|| format!("[{}] missing path", s.name))?;
+        let cell = s.get("cell").unwrap_or("");
+        let (cell_w, cell_h) = match cell.split_once('x') {
+            Some((w, h)) => match (w.trim().parse(), h.trim().parse()) {
+                (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+                _ => return Err(bad("cell", cell)),
+            },
+            None => return Err(bad("cell", cell)),
+        };
+        let columns = match s.get("columns") {
+            Some(v) => match v.parse() {
+                Ok(c) if c > 0 => Some(c),
+                _ => return Err(bad("columns", v)),
+            },
+            None => None,
+        };
+        let first = match s.get("first") {
+            Some(v) => v.parse().map_err(|_| bad("first", v))?,
+            None => 0,
+        };
+        let key = match s.get("key") {
+            Some(v) => {
+                let channels: Vec<u8> = v
+                    .split_whitespace()
+                    .map(|c| c.parse().map_err(|_| bad("key", v)))
+                    .collect::<Result<_, _>>()?;
+                match channels[..] {
+                    [r, g, b] => Some((r, g, b)),
+                    _ => return Err(bad("key", v)),
+                }
+            }
+            None => None,
+        };
+        tilesets.push(Tileset {
+            name: s.name.clone(),
+            path: path.to_string(),
+            cell_w,
+            cell_h,
+            columns,
+            first,
+            key,
+        });
+    }
+    if tilesets.is_empty() {
+        return Err("no tileset".to_string());
+    }
+    Ok(tilesets)
+}
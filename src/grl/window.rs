@@ -0,0 +1,215 @@
+use super::{cp437, Backend, Cell, Terminal, Tileset, UiFont};
+use std::fs;
+use tetra::graphics::mesh::{BufferUsage, IndexBuffer, Mesh, Vertex, VertexBuffer};
+use tetra::graphics::{self, Color, Texture};
+use tetra::math::Vec2;
+use tetra::{window, Context, TetraError};
+
+// Full block, drawn under the glyphs of the first layer
+const BG_TILE: u32 = 219;
+// Drawn for a glyph the tileset doesn't have
+const MISSING_TILE: u32 = '?' as u32;
+// Floats in a vertex, offsets in the vertex buffer are counted in floats
+const VERTEX_FLOATS: usize = 8;
+
+/// Mesh of one layer
+/// Only the cells changed since the last frame are sent to the gpu
+struct LayerMesh {
+    // What the mesh shows, None until the first upload
+    drawn: Vec<Option<Cell>>,
+    // The first layer has a background quad under each glyph
+    background: bool,
+    vertices: Vec<Vertex>,
+    mesh: Mesh,
+}
+
+/// Draw the terminal in the tetra window, with a bitmap tileset
+pub struct Window {
+    tileset: Tileset,
+    texture: Texture,
+    layers: Vec<LayerMesh>,
+    // Draws the text boxes when there is one
+    font: Option<UiFont>,
+}
+
+impl Window {
+    pub fn new(ctx: &mut Context, terminal: &Terminal, tileset: &Tileset) -> tetra::Result<Window> {
+        let ts = load_texture(ctx, tileset)?;
+        let (w, h) = terminal.size();
+        let count = (w * h) as usize;
+
+        // Two triangles for each quad, the same for every layer
+        let mut indices = Vec::with_capacity(count * 2 * 6);
+        for q in 0..(count * 2) as u32 {
+            let v = q * 4;
+            indices.extend_from_slice(&[v, v + 1, v + 2, v + 2, v + 3, v]);
+        }
+        let index_buffer = IndexBuffer::with_usage(ctx, &indices, BufferUsage::Static)?;
+
+        let mut layers = Vec::new();
+        for l in 0..terminal.layers().count() {
+            let background = l == 0;
+            let quads = if background { 2 } else { 1 };
+            let vertices = vec![empty_vertex(); count * quads * 4];
+            let vertex_buffer = VertexBuffer::with_usage(ctx, &vertices, BufferUsage::Dynamic)?;
+            let mut mesh = Mesh::indexed(vertex_buffer, index_buffer.clone());
+            mesh.set_texture(ts.clone());
+            mesh.set_backface_culling(false);
+            mesh.set_draw_range(0, count * quads * 6);
+            layers.push(LayerMesh {
+                drawn: vec![None; count],
+                background,
+                vertices,
+                mesh,
+            });
+        }
+        Ok(Window {
+            tileset: tileset.clone(),
+            texture: ts,
+            layers,
+            font: None,
+        })
+    }
+
+    /// Size of a cell in pixels
+    pub fn cell_size(&self) -> (i32, i32) {
+        (self.tileset.cell_w, self.tileset.cell_h)
+    }
+
+    /// Draw the text boxes with a font, or in the cells with None
+    /// The font used until now is given back
+    pub fn set_font(&mut self, font: Option<UiFont>) -> Option<UiFont> {
+        for layer in self.layers.iter_mut() {
+            for drawn in layer.drawn.iter_mut() {
+                *drawn = None;
+            }
+        }
+        std::mem::replace(&mut self.font, font)
+    }
+
+    pub fn has_font(&self) -> bool {
+        self.font.is_some()
+    }
+
+    /// Draw with another tileset, the window is resized to fit
+    /// Nothing changes if the tileset can't be loaded
+    pub fn set_tileset(&mut self, ctx: &mut Context, terminal: &Terminal, tileset: &Tileset) -> tetra::Result {
+        let texture = load_texture(ctx, tileset)?;
+        for layer in self.layers.iter_mut() {
+            layer.mesh.set_texture(texture.clone());
+            for drawn in layer.drawn.iter_mut() {
+                *drawn = None;
+            }
+        }
+        if let Some(font) = self.font.as_mut() {
+            font.reset();
+        }
+        self.tileset = tileset.clone();
+        self.texture = texture;
+        let (w, h) = terminal.size();
+        window::set_size(ctx, w * tileset.cell_w, h * tileset.cell_h)
+    }
+}
+
+/// Load the image of a tileset, with its key colour made transparent
+fn load_texture(ctx: &mut Context, tileset: &Tileset) -> tetra::Result<Texture> {
+    let key = match tileset.key {
+        Some(key) => key,
+        None => return Texture::new(ctx, &tileset.path),
+    };
+    let data = fs::read(&tileset.path).map_err(|reason| TetraError::FailedToLoadAsset {
+        reason,
+        path: tileset.path.clone().into(),
+    })?;
+    let mut image = image::load_from_memory(&data)
+        .map_err(TetraError::InvalidTexture)?
+        .to_rgba8();
+    for pixel in image.pixels_mut() {
+        if (pixel[0], pixel[1], pixel[2]) == key {
+            pixel[3] = 0;
+        }
+    }
+    Texture::from_rgba(ctx, image.width() as i32, image.height() as i32, &image)
+}
+
+impl Backend for Window {
+    type Target = Context;
+
+    /// Draw every layer, one call each, then the text boxes with the font if any
+    fn present(&mut self, ctx: &mut Context, terminal: &Terminal) {
+        graphics::clear(ctx, Color::rgb8(0, 0, 0));
+        let (w, _) = terminal.size();
+        let (cell_w, cell_h) = self.cell_size();
+        let (tw, th) = self.texture.size();
+        let uv_size = Vec2::new(cell_w as f32 / tw as f32, cell_h as f32 / th as f32);
+        let columns = self.tileset.columns.unwrap_or((tw / cell_w).max(1) as u32);
+        let tiles = columns * (th / cell_h) as u32;
+        let first = self.tileset.first;
+        // Position of a glyph's tile in the image
+        let tile_of = |code: u32| match code.checked_sub(first) {
+            Some(tile) if tile < tiles => tile,
+            _ => MISSING_TILE.saturating_sub(first).min(tiles.saturating_sub(1)),
+        };
+        let cell_size = Vec2::new(cell_w as f32, cell_h as f32);
+        let quad = |code: u32, position: Vec2<f32>, color: Color| -> [Vertex; 4] {
+            let tile = tile_of(code);
+            let uv = Vec2::new((tile % columns) as f32, (tile / columns) as f32) * uv_size;
+            [
+                Vertex::new(position, uv, color),
+                Vertex::new(position + Vec2::new(0.0, cell_size.y), uv + Vec2::new(0.0, uv_size.y), color),
+                Vertex::new(position + cell_size, uv + uv_size, color),
+                Vertex::new(position + Vec2::new(cell_size.x, 0.0), uv + Vec2::new(uv_size.x, 0.0), color),
+            ]
+        };
+
+        for (layer, cells) in self.layers.iter_mut().zip(terminal.layers()) {
+            let quads = if layer.background { 2 } else { 1 };
+            // Range of vertices to upload
+            let mut first = usize::MAX;
+            let mut last = 0;
+            for (i, cell) in cells.iter().enumerate() {
+                if layer.drawn[i].as_ref() == Some(cell) {
+                    continue;
+                }
+                let position = Vec2::new(
+                    (i as i32 % w * cell_w) as f32,
+                    (i as i32 / w * cell_h) as f32,
+                );
+                let start = i * quads * 4;
+                let mut v = start;
+                if layer.background {
+                    layer.vertices[v..v + 4].copy_from_slice(&quad(BG_TILE, position, cell.bg_color));
+                    v += 4;
+                }
+                // Nothing to draw on an empty cell
+                let glyph = if cell.glyph == '\0' {
+                    [empty_vertex(); 4]
+                } else {
+                    let tile = cp437::to_tile(cell.glyph).unwrap_or(MISSING_TILE);
+                    quad(tile, position, cell.fg_color)
+                };
+                layer.vertices[v..v + 4].copy_from_slice(&glyph);
+                layer.drawn[i] = Some(*cell);
+                first = first.min(start);
+                last = v + 4;
+            }
+            if first < last {
+                layer.mesh.vertex_buffer().set_data(
+                    ctx,
+                    &layer.vertices[first..last],
+                    first * VERTEX_FLOATS,
+                );
+            }
+            layer.mesh.draw(ctx, Vec2::new(0.0, 0.0));
+        }
+
+        if let Some(font) = self.font.as_mut() {
+            font.draw(ctx, terminal.text_boxes(), (cell_w, cell_h));
+        }
+    }
+}
+
+/// Collapsed to a point, a quad made of it draws nothing
+fn empty_vertex() -> Vertex {
+    Vertex::new(Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.0), Color::rgba8(0, 0, 0, 0))
+}
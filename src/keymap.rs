@@ -0,0 +1,300 @@
+use crate::config;
+use crate::engine::Direction;
+use std::io;
+use tetra::input::{self, Key};
+use tetra::Context;
+
+pub const KEYMAP_PATH: &str = "./keys.cfg";
+
+/// Letter keys, from A to Z
+pub const LETTERS: [Key; 26] = [
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I,
+    Key::J, Key::K, Key::L, Key::M, Key::N, Key::O, Key::P, Key::Q, Key::R,
+    Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+];
+const DIGITS: [Key; 10] = [
+    Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4,
+    Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+];
+const NUMPAD: [Key; 10] = [
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+];
+// Other keys, named as in the keymap file
+const NAMED: [(&str, Key); 21] = [
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Enter", Key::Enter),
+    ("Escape", Key::Escape),
+    ("Space", Key::Space),
+    ("Tab", Key::Tab),
+    ("Period", Key::Period),
+    ("Comma", Key::Comma),
+    ("Semicolon", Key::Semicolon),
+    ("Slash", Key::Slash),
+    ("LessThan", Key::LessThan),
+    ("GreaterThan", Key::GreaterThan),
+    ("NumPadEnter", Key::NumPadEnter),
+    ("NumPadPlus", Key::NumPadPlus),
+    ("NumPadMinus", Key::NumPadMinus),
+];
+
+/// What the player asks for with a key
+#[derive(Copy, Clone, PartialEq)]
+pub enum Command {
+    Move(Direction),
+    Wait,
+    PickUp,
+    Descend,
+    Ascend,
+    // Take the stairs, whichever way they go
+    Stairs,
+    Inventory,
+    Drop,
+    Explore,
+    History,
+    // Draw with the next tileset
+    Tileset,
+    // Switch the ui text between the TrueType font and the tileset
+    Font,
+    Quit,
+}
+
+/// Keys pressed during the last frame, whatever the frontend
+pub struct Input {
+    keys: Vec<Key>,
+}
+
+impl Input {
+    pub fn new(keys: Vec<Key>) -> Input {
+        Input { keys }
+    }
+
+    pub fn from_context(ctx: &Context) -> Input {
+        Input::new(input::get_keys_pressed(ctx).copied().collect())
+    }
+
+    pub fn is_key_pressed(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+}
+
+/// Keys bound to each command
+pub struct Keymap {
+    bindings: Vec<(Key, Command)>,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        let bindings = vec![
+            // Arrows
+            (Key::Up, Command::Move(Direction::North)),
+            (Key::Down, Command::Move(Direction::South)),
+            (Key::Left, Command::Move(Direction::West)),
+            (Key::Right, Command::Move(Direction::East)),
+            // Numpad
+            (Key::NumPad8, Command::Move(Direction::North)),
+            (Key::NumPad2, Command::Move(Direction::South)),
+            (Key::NumPad4, Command::Move(Direction::West)),
+            (Key::NumPad6, Command::Move(Direction::East)),
+            (Key::NumPad7, Command::Move(Direction::NorthWest)),
+            (Key::NumPad9, Command::Move(Direction::NorthEast)),
+            (Key::NumPad1, Command::Move(Direction::SouthWest)),
+            (Key::NumPad3, Command::Move(Direction::SouthEast)),
+            (Key::NumPad5, Command::Wait),
+            // Vi-keys
+            (Key::K, Command::Move(Direction::North)),
+            (Key::J, Command::Move(Direction::South)),
+            (Key::H, Command::Move(Direction::West)),
+            (Key::L, Command::Move(Direction::East)),
+            (Key::Y, Command::Move(Direction::NorthWest)),
+            (Key::U, Command::Move(Direction::NorthEast)),
+            (Key::B, Command::Move(Direction::SouthWest)),
+            (Key::N, Command::Move(Direction::SouthEast)),
+            (Key::Period, Command::Wait),
+            // Actions
+            (Key::G, Command::PickUp),
+            (Key::Comma, Command::PickUp),
+            (Key::GreaterThan, Command::Descend),
+            (Key::LessThan, Command::Ascend),
+            (Key::Enter, Command::Stairs),
+            (Key::I, Command::Inventory),
+            (Key::D, Command::Drop),
+            (Key::X, Command::Explore),
+            (Key::M, Command::History),
+            (Key::T, Command::Tileset),
+            (Key::F, Command::Font),
+            (Key::Escape, Command::Quit),
+        ];
+        Keymap { bindings }
+    }
+
+    /// Default keys, changed by the `[keys]` section of the file if there is one
+    pub fn load(path: &str) -> io::Result<Keymap> {
+        let mut keymap = Keymap::new();
+        if !std::path::Path::new(path).exists() {
+            return Ok(keymap);
+        }
+        for section in config::read(path)?.iter().filter(|s| s.name == "keys") {
+            for (name, value, line) in section.entries.iter() {
+                let error = |msg: String| config::invalid(path, &format!("line {}: {}", line, msg));
+                let command =
+                    command_from_name(name).ok_or_else(|| error(format!("unknown command `{}`", name)))?;
+                let mut keys = Vec::new();
+                for key_name in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                    keys.push(key_from_name(key_name).ok_or_else(|| error(format!("unknown key `{}`", key_name)))?);
+                }
+                keymap.bind(command, &keys);
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// Replace the keys of a command, the keys are taken from any other command
+    pub fn bind(&mut self, command: Command, keys: &[Key]) {
+        self.bindings
+            .retain(|&(k, c)| c != command && !keys.contains(&k));
+        for &key in keys.iter() {
+            self.bindings.push((key, command));
+        }
+    }
+
+    /// Command of the first bound key pressed
+    pub fn pressed(&self, input: &Input) -> Option<Command> {
+        self.bindings
+            .iter()
+            .find(|&&(key, _)| input.is_key_pressed(key))
+            .map(|&(_, command)| command)
+    }
+
+    pub fn is_pressed(&self, input: &Input, command: Command) -> bool {
+        self.bindings
+            .iter()
+            .any(|&(key, c)| c == command && input.is_key_pressed(key))
+    }
+
+    /// Names of the keys bound to a command, as written in the keymap file
+    pub fn key_names(&self, command: Command) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter(|&&(_, c)| c == command)
+            .map(|&(key, _)| key_name(key))
+            .collect()
+    }
+}
+
+fn command_from_name(name: &str) -> Option<Command> {
+    let command = match name {
+        "move_north" => Command::Move(Direction::North),
+        "move_south" => Command::Move(Direction::South),
+        "move_west" => Command::Move(Direction::West),
+        "move_east" => Command::Move(Direction::East),
+        "move_north_west" => Command::Move(Direction::NorthWest),
+        "move_north_east" => Command::Move(Direction::NorthEast),
+        "move_south_west" => Command::Move(Direction::SouthWest),
+        "move_south_east" => Command::Move(Direction::SouthEast),
+        "wait" => Command::Wait,
+        "pick_up" => Command::PickUp,
+        "descend" => Command::Descend,
+        "ascend" => Command::Ascend,
+        "stairs" => Command::Stairs,
+        "inventory" => Command::Inventory,
+        "drop" => Command::Drop,
+        "explore" => Command::Explore,
+        "history" => Command::History,
+        "tileset" => Command::Tileset,
+        "font" => Command::Font,
+        "quit" => Command::Quit,
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    let upper = name.to_ascii_uppercase();
+    let mut chars = upper.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_uppercase() {
+            return Some(LETTERS[(c as u8 - b'A') as usize]);
+        }
+        if c.is_ascii_digit() {
+            return Some(DIGITS[(c as u8 - b'0') as usize]);
+        }
+    }
+    if let Some(n) = upper.strip_prefix("NUMPAD").and_then(|n| n.parse::<usize>().ok()) {
+        return NUMPAD.get(n).copied();
+    }
+
+    NAMED
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+}
+
+fn key_name(key: Key) -> String {
+    if let Some(i) = LETTERS.iter().position(|&k| k == key) {
+        return ((b'A' + i as u8) as char).to_string();
+    }
+    if let Some(i) = DIGITS.iter().position(|&k| k == key) {
+        return i.to_string();
+    }
+    if let Some(i) = NUMPAD.iter().position(|&k| k == key) {
+        return format!("NumPad{}", i);
+    }
+    NAMED
+        .iter()
+        .find(|&&(_, k)| k == key)
+        .map_or("?", |&(n, _)| n)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Load a keymap from a throwaway file holding `text`
+    fn load_text(name: &str, text: &str) -> io::Result<Keymap> {
+        let path = std::env::temp_dir().join(format!("trogue-{}-{}.cfg", name, std::process::id()));
+        fs::write(&path, text)?;
+        let keymap = Keymap::load(path.to_str().unwrap());
+        fs::remove_file(&path)?;
+        keymap
+    }
+
+    #[test]
+    fn shipped_file_matches_the_defaults() {
+        let loaded = Keymap::load(KEYMAP_PATH).unwrap();
+        let defaults = Keymap::new();
+        assert_eq!(loaded.bindings.len(), defaults.bindings.len());
+        assert!(defaults.bindings.iter().all(|b| loaded.bindings.contains(b)));
+    }
+
+    #[test]
+    fn file_rebinds_commands() {
+        let keymap = load_text("rebind", "[keys]\nwait = space, X\n").unwrap();
+        assert_eq!(keymap.key_names(Command::Wait), vec!["Space", "X"]);
+        // X is taken from explore, the other commands keep their keys
+        assert!(keymap.key_names(Command::Explore).is_empty());
+        assert_eq!(keymap.key_names(Command::History), vec!["M"]);
+    }
+
+    #[test]
+    fn unknown_key_is_an_error() {
+        let error = load_text("unknown", "[keys]\nhistory = F1\n").err().map(|e| e.kind());
+        assert_eq!(error, Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn key_names_read_back() {
+        for name in ["Q", "7", "NumPad3", "PageUp", "GreaterThan"].iter() {
+            assert_eq!(key_name(key_from_name(name).unwrap()), *name);
+        }
+    }
+}
@@ -1,266 +1,1233 @@
 use tetra::graphics::Color;
-use tetra::input::{self, Key};
+use tetra::input::Key;
 use tetra::math::Vec2;
-use tetra::{time, Context, ContextBuilder, Event, State};
-use rand::prelude::*;
+use tetra::{time, window, Context, ContextBuilder, Event, State};
+use std::collections::HashMap;
 
 mod entity;
-use entity::{Entity, EntityType, Tile};
+use entity::{Item, ItemKind, TileType, Tile};
+
+mod ecs;
+use ecs::{Bundle, EntityId, Health, Inventory, Position, World};
 
 mod world;
-use world::*;
+use world::Level;
+
+mod ai;
+
+mod camera;
+use camera::Camera;
 
 mod engine;
-use engine::{Action, Direction};
+use engine::dijkstra::{DijkstraMap, UNREACHABLE};
+use engine::{
+    Action, Blocking, Direction, Fov, FovAlgorithm, GameRng, Occupancy, PathOptions,
+    PathResult, PATH_BUDGET,
+};
+
+mod config;
+
+mod data;
 
 mod grl;
-use grl::Terminal;
+use grl::tileset::{self, TILESETS_PATH};
+use grl::{Backend, Headless, Terminal, TextBox, Tileset, UiFont, Window};
+
+mod keymap;
+use keymap::{Command, Input, Keymap, KEYMAP_PATH, LETTERS};
+
+mod message;
+use message::{MessageLog, Span, DAMAGE_COLOR, TEXT_COLOR};
+
+mod save;
+
+mod scheduler;
+use scheduler::Scheduler;
+
+mod tty;
 
 const VIEW_WIDTH: i32 = 64;
 const VIEW_HEIGHT: i32 = 48;
 const UI_SIZE: i32 = 20;
 const HEIGHT: i32 = VIEW_HEIGHT;
 const WIDTH: i32 = VIEW_WIDTH + UI_SIZE;
-const CELL_SIZE: i32 = 16;
+// Default size of the levels, the camera scrolls over what doesn't fit in the view
+const MAP_WIDTH: i32 = 96;
+const MAP_HEIGHT: i32 = 64;
+// Smallest map the generators can fill
+const MIN_MAP_WIDTH: i32 = 20;
+const MIN_MAP_HEIGHT: i32 = 16;
+// First row of the message log in the UI column
+const LOG_TOP: i32 = 9;
+// TrueType font of the ui text, the F key switches back to the tileset
+const UI_FONT_PATH: &str = "./assets/Hack-Regular.ttf";
+const UI_FONT_SIZE: f32 = 14.0;
 
 struct Mouse {
+    // Screen cell under the pointer
+    screen_x: i32,
+    screen_y: i32,
+    // World cell under it, moves with the camera too
     x: i32,
     y: i32,
+    // Pointer over the map
+    on_map: bool,
     active: bool,
     cell_moved: bool,
     clicked: bool,
 }
 
+/// What the inventory screen is opened for
+#[derive(Copy, Clone, PartialEq)]
+enum InventoryAction {
+    Use,
+    Drop,
+}
+
+/// How `--snapshot` prints the frame
+#[derive(Copy, Clone)]
+enum Snapshot {
+    Text,
+    Ansi,
+}
+
+/// Command line options
+struct Options {
+    // None for a random seed on each run
+    seed: Option<u64>,
+    permadeath: bool,
+    fov_algorithm: FovAlgorithm,
+    snapshot: Option<Snapshot>,
+    tty: bool,
+    map_size: (i32, i32),
+}
+
 struct GameState {
     map_width: i32,
     map_height: i32,
     mouse: Mouse,
+    camera: Camera,
     terminal: Terminal,
     floor_map: Vec<Tile>,
-    npc_list: Vec<Entity>,
-    in_fov: Vec<[i32;2]>,
-    player: Entity,
+    // Entities of the current level, the player included
+    world: World,
+    items: Vec<Item>,
+    occupancy: Occupancy,
+    ai_fields: ai::Fields,
+    in_fov: Fov,
+    fov_algorithm: FovAlgorithm,
+    // Tiles seen for the first time on the last look around
+    newly_visited: Vec<(i32, i32)>,
+    // Item noticed on the last look around
+    spotted_item: Option<&'static str>,
+    // Npc which came into view on the last look around
+    new_hostile: Option<&'static str>,
     path: Vec<Vec2<i32>>,
+    // Why there is no path to the mouse cell
+    path_error: Option<&'static str>,
     auto_walk: bool,
-    turn: u32,
-    player_turn: bool,
+    exploring: bool,
+    // Distance to the unexplored part of the level
+    explore_map: DijkstraMap,
+    scheduler: Scheduler,
+    seed: u64,
+    // Seed given on the command line, reused by every new run
+    fixed_seed: Option<u64>,
+    rng: GameRng,
+    permadeath: bool,
+    // Where the game is saved, the tests use their own files
+    save_path: String,
+    game_over: bool,
+    // Asked to leave, the frontend closes
+    quit: bool,
+    // Asked for the next tileset, the window frontend switches
+    next_tileset: bool,
+    // Asked to switch the ui text font, the window frontend switches
+    switch_font: bool,
+    // The ui text goes in text boxes for the frontend font instead of the cells
+    text_boxes: bool,
+    keymap: Keymap,
+    log: MessageLog,
+    show_history: bool,
+    history_scroll: usize,
+    inventory_screen: Option<InventoryAction>,
+    depth: i32,
+    // Levels the player has left, by depth
+    levels: HashMap<i32, Level>,
 }
 
 impl GameState {
-    fn new(ctx: &mut Context) -> tetra::Result<GameState> {
-        let w = VIEW_WIDTH;
-        let h = VIEW_HEIGHT;
-        let map = world::world_genration(w, h, GenerationType::Cave);
-        let npc = world::spawn_npc(&map, w, h);
-        Ok(GameState {
+    fn new(options: &Options) -> GameState {
+        let (w, h) = options.map_size;
+        let seed = options.seed.unwrap_or_else(rand::random);
+        let mut state = GameState {
             map_width: w,
             map_height: h,
             mouse: Mouse {
+                screen_x: 0,
+                screen_y: 0,
                 x: 1,
                 y: 1,
+                on_map: false,
                 active: false,
                 cell_moved: false,
                 clicked: false,
             },
-            terminal: Terminal::new(ctx, WIDTH, HEIGHT, CELL_SIZE, CELL_SIZE),
-            player: Entity::new(10, 10, EntityType::Player),
+            camera: Camera::new(UI_SIZE, 0, VIEW_WIDTH, VIEW_HEIGHT),
+            terminal: Terminal::new(WIDTH, HEIGHT),
             path: Vec::new(),
+            path_error: None,
             auto_walk: false,
-            floor_map: map,
-            npc_list: npc,
-            in_fov: Vec::new(),
-            turn: 0,
-            player_turn: true,
-        })
+            exploring: false,
+            explore_map: DijkstraMap::new(w, h, true),
+            floor_map: Vec::new(),
+            world: World::new(),
+            items: Vec::new(),
+            occupancy: Occupancy::new(w, h),
+            ai_fields: ai::Fields::new(w, h),
+            in_fov: Fov::new(w, h),
+            fov_algorithm: options.fov_algorithm,
+            newly_visited: Vec::new(),
+            spotted_item: None,
+            new_hostile: None,
+            scheduler: Scheduler::new(),
+            seed,
+            fixed_seed: options.seed,
+            rng: engine::new_rng(seed),
+            permadeath: options.permadeath,
+            save_path: save::SAVE_PATH.to_string(),
+            game_over: false,
+            quit: false,
+            next_tileset: false,
+            switch_font: false,
+            text_boxes: false,
+            keymap: Keymap::load(KEYMAP_PATH).unwrap_or_else(|e| {
+                eprintln!("Can't load {}: {}", KEYMAP_PATH, e);
+                Keymap::new()
+            }),
+            log: MessageLog::new(),
+            show_history: false,
+            history_scroll: 0,
+            inventory_screen: None,
+            depth: 0,
+            levels: HashMap::new(),
+        };
+        state.new_run(seed);
+        state
+    }
+
+    /// Start a new game from scratch
+    fn new_run(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = engine::new_rng(seed);
+        self.levels.clear();
+        self.depth = 0;
+        self.world = World::new();
+        self.inventory_screen = None;
+        self.scheduler = Scheduler::new();
+        self.game_over = false;
+        self.log.clear();
+        self.change_level(1);
+        self.log.info("You wake up in a dark cave");
+    }
+
+    /// Leave the current level for the one at the given depth
+    /// The level left is kept, the new one is generated on first visit
+    fn change_level(&mut self, depth: i32) {
+        let going_down = depth > self.depth;
+        // The player goes along, a new one is made for a new run
+        let mut player = match self.world.player() {
+            Some(id) => self.world.despawn(id),
+            None => Bundle::player(0, 0),
+        };
+        if self.depth > 0 {
+            let left = Level {
+                depth: self.depth,
+                floor_map: std::mem::take(&mut self.floor_map),
+                world: std::mem::replace(&mut self.world, World::new()),
+                items: std::mem::take(&mut self.items),
+            };
+            self.levels.insert(self.depth, left);
+        }
+
+        let level = match self.levels.remove(&depth) {
+            Some(level) => level,
+            None => world::generate_level(depth, self.map_width, self.map_height, &mut self.rng),
+        };
+        self.depth = level.depth;
+        self.floor_map = level.floor_map;
+        self.world = level.world;
+        self.items = level.items;
+        for (_, renderable) in self.world.renderables.iter_mut() {
+            renderable.in_view = false;
+        }
+
+        // Arrive on the matching stairs
+        let stairs = if going_down {
+            TileType::StairsUp
+        } else {
+            TileType::StairsDown
+        };
+        let last = player.position.map_or((0, 0), |p| (p.x, p.y));
+        let (x, y) = world::find_tile(&self.floor_map, stairs).unwrap_or(last);
+        let (x, y) = self.free_cell_near(x, y);
+        player.position = Some(Position { x, y });
+        self.world.spawn(player);
+
+        self.path.clear();
+        self.auto_walk = false;
+        self.exploring = false;
+        self.in_fov.clear();
+        for tile in self.floor_map.iter_mut() {
+            tile.visible = false;
+        }
+        self.ai_fields = ai::Fields::new(self.map_width, self.map_height);
+        self.explore_map = DijkstraMap::new(self.map_width, self.map_height, true);
+        self.refresh_occupancy();
+    }
+
+    /// Nearest crossable cell without npc
+    fn free_cell_near(&self, x: i32, y: i32) -> (i32, i32) {
+        let (w, h) = (self.map_width, self.map_height);
+        for r in 0..w.max(h) {
+            for dx in -r..=r {
+                for dy in -r..=r {
+                    let (cx, cy) = (x + dx, y + dy);
+                    if dx.abs().max(dy.abs()) != r || cx < 0 || cx >= w || cy < 0 || cy >= h {
+                        continue;
+                    }
+                    if self.floor_map[(cx + cy * w) as usize].crossable
+                        && self.world.blocker_at(cx, cy).is_none()
+                    {
+                        return (cx, cy);
+                    }
+                }
+            }
+        }
+        (x, y)
+    }
+
+    fn player(&self) -> EntityId {
+        self.world.player().expect("the player is in the world")
+    }
+
+    fn player_pos(&self) -> Position {
+        *self.world.positions.get(self.player()).expect("the player has a position")
+    }
+
+    fn player_health(&self) -> Health {
+        *self.world.healths.get(self.player()).expect("the player has health")
+    }
+
+    /// The player's pack
+    fn pack(&self) -> &Inventory {
+        self.world.inventories.get(self.player()).expect("the player has a pack")
+    }
+
+    fn pack_mut(&mut self) -> &mut Inventory {
+        let player = self.player();
+        self.world.inventories.get_mut(player).expect("the player has a pack")
+    }
+
+    fn player_tile(&self) -> TileType {
+        let p = self.player_pos();
+        self.floor_map[(p.x + p.y * self.map_width) as usize].tile_type
+    }
+
+    /// Rebuild the occupancy layer, needed when npc are added or removed
+    fn refresh_occupancy(&mut self) {
+        self.occupancy = Occupancy::from_world(&self.world, self.map_width, self.map_height);
+    }
+
+    /// The run is over, with permadeath the save goes with the hero
+    fn player_death(&mut self) {
+        self.game_over = true;
+        self.log.add(vec![Span::new("You die...", DAMAGE_COLOR)]);
+        self.path.clear();
+        self.auto_walk = false;
+        self.exploring = false;
+        if self.permadeath {
+            if let Err(e) = save::delete_save(&self.save_path) {
+                eprintln!("Can't delete {}: {}", self.save_path, e);
+            }
+        }
+    }
+
+    /// Continue the saved game if there is one
+    fn restore(&mut self) {
+        if !std::path::Path::new(&self.save_path).exists() {
+            return;
+        }
+        if let Err(e) = save::read_save(&self.save_path.clone(), self) {
+            eprintln!("Can't load {}: {}", self.save_path, e);
+        }
+        self.in_fov = Fov::new(self.map_width, self.map_height);
+        self.ai_fields = ai::Fields::new(self.map_width, self.map_height);
+        self.explore_map = DijkstraMap::new(self.map_width, self.map_height, true);
+        self.refresh_occupancy();
+    }
+
+    /// Save on the way out, a dead hero stays dead
+    fn save_on_exit(&self) {
+        if !self.game_over {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        if let Err(e) = save::write_save(&self.save_path, self) {
+            eprintln!("Can't save {}: {}", self.save_path, e);
+        }
     }
     fn action_manager(&mut self, action: Action, dir: Direction) {
+        let player = self.player();
+        if !scheduler::can_act(&self.world, player) {
+            return;
+        }
+        let p = self.player_pos();
         match action {
             Action::Move => {
                 // Switch mouse to inactive if key down
                 self.mouse.active = false;
-                if engine::check_crossable_destination(
-                    self.player.x,
-                    self.player.y,
+                let (x, y) = engine::destination(p.x, p.y, dir);
+                if self.occupancy.get(x, y).is_some_and(|id| id != player) {
+                    // Bump to attack
+                    self.action_manager(Action::Attack, dir);
+                } else if engine::check_crossable_destination(
+                    p.x,
+                    p.y,
                     dir,
                     &self.floor_map,
+                    &self.occupancy,
                     self.map_width,
                     self.map_height
                 ) {
-                    engine::move_entity(&mut self.player, dir);
+                    self.occupancy.move_occupant((p.x, p.y), (x, y));
+                    engine::move_entity(&mut self.world, player, dir);
 
-                    self.player_turn = false;
+                    if let Some(item) = self.items.iter().find(|i| i.x == x && i.y == y) {
+                        let spans = vec![Span::text("You see here "), Span::new(item.name(), item.fg_color)];
+                        self.log.add(spans);
+                    }
+
+                    scheduler::spend(&mut self.world, player, &action);
+                }
+            }
+            Action::Attack => {
+                let (x, y) = engine::destination(p.x, p.y, dir);
+                // Never the player itself, when there is no direction
+                if let Some(id) = self.occupancy.get(x, y).filter(|&id| id != player) {
+                    let damage = engine::attack(&mut self.world, player, id, &mut self.rng);
+                    let target = Span::new(self.world.name(id), self.world.color(id));
+                    if damage > 0 {
+                        self.log.add(vec![
+                            Span::text("You hit the "),
+                            target.clone(),
+                            Span::text(" for "),
+                            Span::new(&damage.to_string(), DAMAGE_COLOR),
+                        ]);
+                    } else {
+                        self.log.add(vec![Span::text("You miss the "), target.clone()]);
+                    }
+                    if self.world.healths.get(id).is_some_and(|h| h.is_dead()) {
+                        self.log.add(vec![Span::text("The "), target, Span::text(" dies")]);
+                        self.world.despawn(id);
+                        self.refresh_occupancy();
+                    }
+                }
+                scheduler::spend(&mut self.world, player, &action);
+            }
+            Action::Wait => {
+                self.mouse.active = false;
+                scheduler::spend(&mut self.world, player, &action);
+            }
+            Action::Descend => {
+                if self.player_tile() == TileType::StairsDown {
+                    self.change_level(self.depth + 1);
+                    self.log.info(&format!("You go down to depth {}", self.depth));
+                    scheduler::spend(&mut self.world, player, &action);
+                } else {
+                    self.log.info("There are no stairs down here");
+                }
+            }
+            Action::Ascend => {
+                if self.player_tile() != TileType::StairsUp {
+                    self.log.info("There are no stairs up here");
+                } else if self.depth == 1 {
+                    self.log.info("The way out is blocked by rubble");
+                } else {
+                    self.change_level(self.depth - 1);
+                    self.log.info(&format!("You climb up to depth {}", self.depth));
+                    scheduler::spend(&mut self.world, player, &action);
+                }
+            }
+            Action::PickUp => {
+                let (x, y) = (p.x, p.y);
+                match self.items.iter().position(|i| i.x == x && i.y == y) {
+                    Some(index) => {
+                        let item = self.items.remove(index);
+                        let name = Span::new(item.name(), item.fg_color);
+                        if item.kind() == ItemKind::Gold {
+                            self.pack_mut().gold += item.quantity;
+                            self.log.add(vec![
+                                Span::text(&format!("You pick up {} ", item.quantity)),
+                                name,
+                            ]);
+                        } else {
+                            self.log.add(vec![Span::text("You pick up a "), name]);
+                            self.pack_mut().items.push(item);
+                        }
+                        scheduler::spend(&mut self.world, player, &action);
+                    }
+                    None => self.log.info("There is nothing here"),
+                }
+            }
+            Action::Drop(index) => {
+                if index < self.pack().items.len() {
+                    let mut item = self.pack_mut().items.remove(index);
+                    item.x = p.x;
+                    item.y = p.y;
+                    item.equipped = false;
+                    self.log.add(vec![Span::text("You drop the "), Span::new(item.name(), item.fg_color)]);
+                    self.items.push(item);
+                    scheduler::spend(&mut self.world, player, &action);
+                }
+            }
+            Action::Use(index) => {
+                if index < self.pack().items.len() {
+                    self.use_item(index);
+                    scheduler::spend(&mut self.world, player, &action);
                 }
             }
         }
     }
-}
 
-impl State for GameState {
-    fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
-        // Cornflower blue, as is tradition
-        self.terminal.clear(ctx);
+    /// Drink a potion, or equip / unequip an equipment
+    fn use_item(&mut self, index: usize) {
+        let item = self.pack().items[index].clone();
+        let name = Span::new(item.name(), item.fg_color);
+        if item.is_equipment() {
+            if item.equipped {
+                self.pack_mut().items[index].equipped = false;
+                self.log.add(vec![Span::text("You take off the "), name]);
+            } else {
+                // Only one equipment of each kind
+                for i in self.pack_mut().items.iter_mut() {
+                    if i.kind() == item.kind() {
+                        i.equipped = false;
+                    }
+                }
+                self.pack_mut().items[index].equipped = true;
+                self.log.add(vec![Span::text("You equip the "), name]);
+            }
+        } else if item.kind() == ItemKind::Potion {
+            let player = self.player();
+            if let Some(health) = self.world.healths.get_mut(player) {
+                health.hp = (health.hp + item.bonus()).min(health.max_hp);
+            }
+            self.pack_mut().items.remove(index);
+            self.log.add(vec![Span::text("You drink the "), name, Span::text(" and feel better")]);
+        }
+    }
 
+    /// Inventory listed over the map
+    fn draw_inventory(&mut self, action: InventoryAction) {
+        let x = UI_SIZE + 2;
         self.terminal.layer(0);
-        // Map display
+        self.terminal.bg_color(Color::rgb8(0, 0, 0));
+        // Hide the map
+        for i in 0..VIEW_WIDTH {
+            for j in 0..VIEW_HEIGHT {
+                self.terminal.put(UI_SIZE + i, j, '\0');
+            }
+        }
+        self.terminal.fg_color(Color::rgb8(255, 255, 255));
+        let title = match action {
+            InventoryAction::Use => "Inventory - choose an item to use",
+            InventoryAction::Drop => "Inventory - choose an item to drop",
+        };
+        self.terminal.print(x, 1, title.to_string());
+        let items = self.pack().items.clone();
+        if items.is_empty() {
+            self.terminal.print(x, 3, "Your pack is empty".to_string());
+        }
+        for (i, item) in items.iter().enumerate().take(LETTERS.len()) {
+            let letter = (b'a' + i as u8) as char;
+            let equipped = if item.equipped { " (equipped)" } else { "" };
+            self.terminal.fg_color(item.fg_color);
+            self.terminal
+                .print(x, 3 + i as i32, format!("{}) {}{}", letter, item.name(), equipped));
+        }
+        self.terminal.fg_color(Color::rgb8(150, 150, 150));
+        self.terminal.print(x, VIEW_HEIGHT - 2, "Escape to close".to_string());
+    }
 
+    /// Update the player's fov and tell about what comes into view
+    fn look_around(&mut self) {
+        const RANGE: i32 = 10;
+        let (w, h) = (self.map_width, self.map_height);
+        let p = self.player_pos();
+
+        // Tiles not seen yet around the player
+        let mut unknown = Vec::new();
+        for x in (p.x - RANGE).max(0)..(p.x + RANGE + 1).min(w) {
+            for y in (p.y - RANGE).max(0)..(p.y + RANGE + 1).min(h) {
+                if !self.floor_map[(x + y * w) as usize].visited {
+                    unknown.push((x, y));
+                }
+            }
+        }
+
+        engine::fov(
+            p.x,
+            p.y,
+            RANGE,
+            &mut self.floor_map,
+            &mut self.in_fov,
+            self.fov_algorithm,
+            w,
+        );
+
+        let floor_map = &self.floor_map;
+        unknown.retain(|&(x, y)| floor_map[(x + y * w) as usize].visited);
+        if unknown
+            .iter()
+            .any(|&(x, y)| floor_map[(x + y * w) as usize].tile_type == TileType::Door)
+        {
+            self.log.info("You see a door");
+        }
+        self.newly_visited = unknown;
+
+        self.new_hostile = None;
+        let ids: Vec<EntityId> = self.world.entities().collect();
+        for id in ids {
+            let visible = match self.world.positions.get(id) {
+                Some(p) => self.in_fov.contains(p.x, p.y),
+                None => false,
+            };
+            let renderable = match self.world.renderables.get_mut(id) {
+                Some(renderable) => renderable,
+                None => continue,
+            };
+            let spotted = visible && !renderable.in_view;
+            renderable.in_view = visible;
+            if spotted && !self.world.is_player(id) {
+                self.new_hostile = Some(self.world.name(id));
+                self.log.add(vec![
+                    Span::text("You see a "),
+                    Span::new(self.world.name(id), self.world.color(id)),
+                ]);
+            }
+        }
+
+        self.spotted_item = None;
+        for item in self.items.iter_mut() {
+            if !item.seen && self.in_fov.contains(item.x, item.y) {
+                item.seen = true;
+                self.spotted_item = Some(item.name());
+            }
+        }
+    }
+
+    /// Stop walking or exploring on its own, and tell why
+    fn interrupt(&mut self, reason: &str) {
+        self.auto_walk = false;
+        self.exploring = false;
+        self.log.info(reason);
+    }
+
+    fn next_step_free(&self) -> bool {
+        match self.path.first() {
+            Some(step) => {
+                self.floor_map[(step.x + step.y * self.map_width) as usize].crossable
+                    && !self.occupancy.is_occupied(step.x, step.y)
+            }
+            None => true,
+        }
+    }
+
+    /// Something stands in the way, look for another way to the same place
+    fn replan(&mut self) {
+        let goal = match self.path.last() {
+            Some(goal) => (goal.x, goal.y),
+            None => return,
+        };
+        // No other way when the goal itself is taken
+        if self.occupancy.is_occupied(goal.0, goal.1) {
+            self.interrupt("Your way is blocked, you stop");
+            return;
+        }
+        let options = PathOptions {
+            blocking: Blocking::Block,
+            known_only: true,
+            budget: PATH_BUDGET,
+        };
+        let p = self.player_pos();
+        match engine::path_finder(
+            (p.x, p.y),
+            goal,
+            &self.floor_map,
+            &self.occupancy,
+            options,
+            self.map_width,
+            self.map_height,
+        ) {
+            PathResult::Found(path) => self.path = path,
+            _ => self.interrupt("Your way is blocked, you stop"),
+        }
+    }
+
+    /// Visited floor next to a tile never seen
+    fn frontier(&self) -> Vec<(i32, i32)> {
+        let (w, h) = (self.map_width, self.map_height);
+        let mut frontier = Vec::new();
         for tile in self.floor_map.iter() {
-            if tile.visible {
-                self.terminal.fg_color(tile.fg_color);
-                self.terminal.bg_color(tile.bg_color);
-                self.terminal.put(UI_SIZE + tile.x, tile.y, tile.glyph);
-            } else if tile.visited {
-                let fg = engine::visited_color(tile.fg_color);
-                let bg = engine::visited_color(tile.bg_color);
-                self.terminal.fg_color(Color::rgb8(fg.0, fg.1, fg.2));
-                self.terminal.bg_color(Color::rgb8(bg.0, bg.1, bg.2));
-                self.terminal.put(UI_SIZE + tile.x, tile.y, tile.glyph);
+            if !tile.visited || !tile.crossable {
+                continue;
             }
+            let unknown_around = (-1..=1).any(|dx| {
+                (-1..=1).any(|dy| {
+                    let (x, y) = (tile.x + dx, tile.y + dy);
+                    x >= 0 && x < w && y >= 0 && y < h && !self.floor_map[(x + y * w) as usize].visited
+                })
+            });
+            if unknown_around {
+                frontier.push((tile.x, tile.y));
+            }
+        }
+        frontier
+    }
+
+    /// One step of auto-explore, toward the nearest tile never seen
+    fn explore(&mut self) {
+        let world = &self.world;
+        let in_view = world.renderables.iter().find(|&(id, r)| r.in_view && !world.is_player(id));
+        if let Some((id, _)) = in_view {
+            let reason = format!("You stop exploring, a {} is in view", world.name(id));
+            self.interrupt(&reason);
+            return;
+        }
+        if let Some(name) = self.spotted_item {
+            self.interrupt(&format!("You stop exploring, you spot a {}", name));
+            return;
         }
 
-        for n in self.npc_list.iter() {
-            if self.in_fov.iter().any(|&t| t[0] == n.x && t[1] == n.y) {
-                let npc_index = (n.y * self.map_width + n.x) as usize;
-                self.terminal
-                    .bg_color(self.floor_map[npc_index as usize].bg_color);
-                self.terminal.fg_color(n.fg_color);
-                self.terminal
-                    .put(UI_SIZE + n.x, n.y, n.glyph);
+        let frontier = self.frontier();
+        if frontier.is_empty() {
+            self.interrupt("This level is fully explored");
+            return;
+        }
+        // Same goals, but newly seen tiles may open a shorter way
+        if !self.explore_map.set_goals(&self.floor_map, &frontier) {
+            self.explore_map.refresh(&self.floor_map, &self.newly_visited);
+        }
+
+        let Position { x, y } = self.player_pos();
+        if self.explore_map.get(x, y) == UNREACHABLE {
+            self.interrupt("Nothing left to explore within reach");
+            return;
+        }
+        let occupancy = &self.occupancy;
+        match self.explore_map.downhill(x, y, |x, y| !occupancy.is_occupied(x, y)) {
+            Some(step) => self.action_manager(Action::Move, engine::orientation(step)),
+            None => self.interrupt("You stop exploring, something is in the way"),
+        }
+    }
 
+    /// Path from the player to the mouse cell, through known tiles
+    fn update_path(&mut self) {
+        let options = PathOptions {
+            blocking: Blocking::Cost(10),
+            known_only: true,
+            budget: PATH_BUDGET,
+        };
+        let p = self.player_pos();
+        let result = engine::path_finder(
+            (p.x, p.y),
+            (self.mouse.x, self.mouse.y),
+            &self.floor_map,
+            &self.occupancy,
+            options,
+            self.map_width,
+            self.map_height,
+        );
+        self.path_error = None;
+        match result {
+            PathResult::Found(path) => self.path = path,
+            PathResult::NoPath => {
+                self.path.clear();
+                self.path_error = Some("You know no way there");
+            }
+            PathResult::BudgetExhausted => {
+                self.path.clear();
+                self.path_error = Some("That is too far away");
             }
         }
+    }
 
-        // Player display
-        let player_index = (self.player.y * self.map_width + self.player.x) as usize;
-        self.terminal
-            .bg_color(self.floor_map[player_index].bg_color);
-        self.terminal.fg_color(self.player.fg_color);
-        self.terminal
-            .put(UI_SIZE + self.player.x, self.player.y, self.player.glyph);
+    /// Print colored spans on one line
+    fn print_spans(&mut self, x: i32, y: i32, spans: &[Span]) {
+        let mut x = x;
+        for span in spans.iter() {
+            self.terminal.fg_color(span.color);
+            self.terminal.print(x, y, span.text.clone());
+            x += span.text.chars().count() as i32;
+        }
+    }
 
+    /// Full screen view of all the messages
+    fn draw_history(&mut self) {
+        self.terminal.layer(0);
         self.terminal.bg_color(Color::rgb8(0, 0, 0));
+        self.terminal.fg_color(Color::rgb8(255, 255, 255));
+        // Escape closes it too
+        let mut close = self.keymap.key_names(Command::History);
+        if !close.iter().any(|k| k == "Escape") {
+            close.push("Escape".to_string());
+        }
         self.terminal.print(
+            1,
             0,
-            0,
-            format!("Position {} - {}", self.player.x, self.player.y),
+            format!("Message history - Up/Down to scroll, {} to close", close.join("/")),
         );
-        self.terminal
-            .print(0, 1, format!("Mouse {} - {}", self.mouse.x, self.mouse.y));
-        self.terminal.print(0, 2, format!("Turn {}", self.turn));
-        self.terminal
-            .print(0, 3, format!("FPS {}", time::get_fps(ctx) as i32));
 
-        self.terminal.layer(1);
-        // Draw path
-        if self.mouse.active {
-            self.terminal.fg_color(Color::rgba8(255, 255, 0, 50));
-            if self.mouse.cell_moved {
-                self.path = engine::path_finder(
-                    self.player.x,
-                    self.player.y,
-                    self.mouse.x,
-                    self.mouse.y,
-                    &self.floor_map,
-                    self.map_width,
-                    self.map_height,
-                );
-                self.path.reverse();
+        let lines = self.log.lines((WIDTH - 2) as usize);
+        let rows = (HEIGHT - 2) as usize;
+        self.history_scroll = self.history_scroll.min(lines.len().saturating_sub(rows));
+        let end = lines.len() - self.history_scroll;
+        let start = end.saturating_sub(rows);
+        for (row, line) in lines[start..end].iter().enumerate() {
+            self.print_spans(1, 2 + row as i32, line);
+        }
+    }
 
-                // Remove starting point from the path
-                if !self.path.is_empty() {
+    /// Run the clock of one tick, npc with enough energy take their turn
+    fn tick(&mut self) {
+        self.scheduler.tick(&mut self.world);
+        self.ai_fields.update(&self.floor_map, self.player_pos());
+        let npcs: Vec<EntityId> = self.world.ais.iter().map(|(id, _)| id).collect();
+        for id in npcs {
+            while scheduler::can_act(&self.world, id) {
+                let action = self.npc_act(id);
+                scheduler::spend(&mut self.world, id, &action);
+            }
+        }
+    }
 
-                    self.path.remove(0);
+    /// Let the ai choose and do the npc action, return what was done
+    fn npc_act(&mut self, id: EntityId) -> Action {
+        let player = self.player();
+        let dir = ai::take_turn(
+            &mut self.world,
+            id,
+            player,
+            &self.floor_map,
+            &self.occupancy,
+            &mut self.ai_fields,
+            &mut self.rng,
+        );
+        let npc = match self.world.positions.get(id) {
+            Some(&p) => p,
+            None => return Action::Wait,
+        };
+        let (x, y) = engine::destination(npc.x, npc.y, dir);
+        if self.occupancy.get(x, y) == Some(player) {
+            let damage = engine::attack(&mut self.world, id, player, &mut self.rng);
+            let attacker = Span::new(self.world.name(id), self.world.color(id));
+            if damage > 0 {
+                self.log.add(vec![
+                    Span::text("The "),
+                    attacker,
+                    Span::text(" hits you for "),
+                    Span::new(&damage.to_string(), DAMAGE_COLOR),
+                ]);
+            } else {
+                self.log.add(vec![Span::text("The "), attacker, Span::text(" misses you")]);
+            }
+            Action::Attack
+        } else if engine::check_crossable_destination(npc.x, npc.y, dir, &self.floor_map, &self.occupancy, self.map_width, self.map_height) {
+            self.occupancy.move_occupant((npc.x, npc.y), (x, y));
+            engine::move_entity(&mut self.world, id, dir);
+            Action::Move
+        } else {
+            Action::Wait
+        }
+    }
+}
+
+/// The game in a tetra window
+struct App {
+    game: GameState,
+    window: Window,
+    tilesets: Vec<Tileset>,
+    // Index of the last tileset chosen
+    tileset: usize,
+    // Ui font put aside while the tileset draws the text
+    font: Option<UiFont>,
+}
+
+impl App {
+    /// Switch to the next tileset, a broken one is reported and skipped
+    fn next_tileset(&mut self, ctx: &mut Context) {
+        self.tileset = (self.tileset + 1) % self.tilesets.len();
+        let tileset = &self.tilesets[self.tileset];
+        match self.window.set_tileset(ctx, &self.game.terminal, tileset) {
+            Ok(()) => self.game.log.info(&format!("Tileset {}", tileset.name)),
+            Err(e) => self.game.log.info(&format!("Can't load the tileset {}: {}", tileset.name, e)),
+        }
+    }
+
+    /// Draw the ui text with the TrueType font or with the tileset
+    fn switch_font(&mut self) {
+        self.font = self.window.set_font(self.font.take());
+        self.game.text_boxes = self.window.has_font();
+        if self.window.has_font() {
+            self.game.log.info("Ui text drawn with the TrueType font");
+        } else {
+            self.game.log.info("Ui text drawn with the tileset");
+        }
+    }
+}
+
+/// Leaving the game saves it, whether by Escape or by closing the window
+impl Drop for App {
+    fn drop(&mut self) {
+        self.game.save_on_exit();
+    }
+}
+
+impl State for App {
+    fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
+        self.game.render(time::get_fps(ctx) as i32);
+        self.window.present(ctx, &self.game.terminal);
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
+        self.game.update(&Input::from_context(ctx));
+        if self.game.quit {
+            window::quit(ctx);
+        }
+        if self.game.next_tileset {
+            self.game.next_tileset = false;
+            self.next_tileset(ctx);
+        }
+        if self.game.switch_font {
+            self.game.switch_font = false;
+            self.switch_font();
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, _: &mut Context, event: Event) -> tetra::Result {
+        self.game.event(event, self.window.cell_size());
+        Ok(())
+    }
+}
+
+impl GameState {
+    /// Fill the terminal with the current frame
+    fn render(&mut self, fps: i32) {
+        self.terminal.clear();
+
+        if self.show_history {
+            self.draw_history();
+            return;
+        }
+
+        self.terminal.layer(0);
+        self.aim_mouse();
+        // Map display, only the part in view
+
+        for sy in 0..VIEW_HEIGHT {
+            for sx in 0..VIEW_WIDTH {
+                let (x, y) = match self.camera.to_world(UI_SIZE + sx, sy) {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                let tile = &self.floor_map[(y * self.map_width + x) as usize];
+                if tile.visible {
+                    self.terminal.fg_color(tile.fg_color);
+                    self.terminal.bg_color(tile.bg_color);
+                    self.terminal.put(UI_SIZE + sx, sy, tile.glyph);
+                } else if tile.visited {
+                    let fg = engine::visited_color(tile.fg_color);
+                    let bg = engine::visited_color(tile.bg_color);
+                    self.terminal.fg_color(Color::rgb8(fg.0, fg.1, fg.2));
+                    self.terminal.bg_color(Color::rgb8(bg.0, bg.1, bg.2));
+                    self.terminal.put(UI_SIZE + sx, sy, tile.glyph);
+                }
+            }
+        }
+
+        for item in self.items.iter() {
+            let tile = &self.floor_map[(item.y * self.map_width + item.x) as usize];
+            if let (true, Some((sx, sy))) = (tile.visible, self.camera.to_screen(item.x, item.y)) {
+                self.terminal.bg_color(tile.bg_color);
+                self.terminal.fg_color(item.fg_color);
+                self.terminal.put(sx, sy, item.glyph);
+            }
+        }
+
+        // The player last, so it is always on top
+        let player = self.player();
+        let others = self.world.entities().filter(|&id| id != player);
+        for id in others.chain(std::iter::once(player)) {
+            if let (Some(p), Some(r)) = (self.world.positions.get(id), self.world.renderables.get(id)) {
+                if let (true, Some((sx, sy))) = (r.in_view, self.camera.to_screen(p.x, p.y)) {
+                    let index = (p.y * self.map_width + p.x) as usize;
+                    self.terminal.bg_color(self.floor_map[index].bg_color);
+                    self.terminal.fg_color(r.fg_color);
+                    self.terminal.put(sx, sy, r.glyph);
                 }
             }
+        }
+
+        let p = self.player_pos();
+        let health = self.player_health();
+        let status = vec![
+            format!("Position {} - {}", p.x, p.y),
+            format!("Mouse {} - {}", self.mouse.x, self.mouse.y),
+            format!("Turn {}", self.scheduler.turn()),
+            format!("FPS {}", fps),
+            // A seed takes up to 20 digits, the whole width of the panel
+            "Seed".to_string(),
+            self.seed.to_string(),
+            format!("Depth {}", self.depth),
+            format!("HP {}/{}", health.hp, health.max_hp),
+            format!("Gold {}", self.pack().gold),
+        ];
+        self.terminal.bg_color(Color::rgb8(0, 0, 0));
+        self.terminal.fg_color(TEXT_COLOR);
+        if self.text_boxes {
+            self.terminal.text_box(TextBox {
+                x: 0,
+                y: 0,
+                w: UI_SIZE,
+                h: LOG_TOP,
+                lines: status.into_iter().map(|line| vec![(line, TEXT_COLOR)]).collect(),
+                from_bottom: false,
+            });
+        } else {
+            // The status lines stay in the ui panel
+            self.terminal.clip(0, 0, UI_SIZE, HEIGHT);
+            for (row, line) in status.into_iter().enumerate() {
+                self.terminal.print(0, row as i32, line);
+            }
+            self.terminal.unclip();
+        }
+
+        // Message log, the newest at the bottom
+        if self.text_boxes {
+            self.terminal.text_box(TextBox {
+                x: 0,
+                y: LOG_TOP,
+                w: UI_SIZE,
+                h: HEIGHT - LOG_TOP,
+                lines: self.log.parts(),
+                from_bottom: true,
+            });
+        } else {
+            let lines = self.log.lines(UI_SIZE as usize);
+            let rows = (HEIGHT - LOG_TOP) as usize;
+            let start = lines.len().saturating_sub(rows);
+            for (row, line) in lines[start..].iter().enumerate() {
+                self.print_spans(0, LOG_TOP + row as i32, line);
+            }
+        }
+
+        if self.game_over {
+            self.terminal.fg_color(Color::rgb8(200, 30, 30));
+            self.terminal.print(UI_SIZE + VIEW_WIDTH / 2 - 5, VIEW_HEIGHT / 2, "GAME OVER".to_string());
+            self.terminal.fg_color(Color::rgb8(200, 200, 200));
+            self.terminal.print(
+                UI_SIZE + VIEW_WIDTH / 2 - 14,
+                VIEW_HEIGHT / 2 + 2,
+                format!("You died on turn {}", self.scheduler.turn()),
+            );
+            self.terminal.print(
+                UI_SIZE + VIEW_WIDTH / 2 - 14,
+                VIEW_HEIGHT / 2 + 3,
+                "Press Enter for a new run".to_string(),
+            );
+        }
+
+        if let Some(action) = self.inventory_screen {
+            self.draw_inventory(action);
+        }
+
+        self.terminal.layer(1);
+        // Draw path
+        if self.mouse.active && self.mouse.on_map {
+            self.terminal.fg_color(Color::rgba8(255, 255, 0, 50));
             for step in self.path.iter() {
-                self.terminal.put(UI_SIZE + step.x, step.y, 219);
+                if let Some((sx, sy)) = self.camera.to_screen(step.x, step.y) {
+                    self.terminal.put(sx, sy, '█');
+                }
             }
         }
         // Mouse Display
-        if self.mouse.active {
+        if self.mouse.active && self.mouse.on_map {
             self.terminal.fg_color(Color::rgba8(255, 255, 0, 100));
-            self.terminal.put(UI_SIZE + self.mouse.x, self.mouse.y, 219);
+            if let Some((sx, sy)) = self.camera.to_screen(self.mouse.x, self.mouse.y) {
+                self.terminal.put(sx, sy, '█');
+            }
         }
 
         self.terminal.bg_color(Color::rgb8(0, 0, 0));
-        self.terminal.refresh(ctx);
-        Ok(())
     }
 
-    fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        if self.player_turn {
-            self.in_fov = engine::fov(
-                self.player.x,
-                self.player.y,
-                10,
-                &mut self.floor_map,
-                self.map_width,
-                self.map_height,
-            );
-
-            if !self.path.is_empty() && self.mouse.clicked {
-                self.auto_walk = true;
-            } else if self.path.is_empty() {
-                self.auto_walk = false;
+    /// Play one frame with the keys pressed during it
+    fn update(&mut self, input: &Input) {
+        if self.game_over {
+            if input.is_key_pressed(Key::Enter) {
+                self.new_run(self.fixed_seed.unwrap_or_else(rand::random));
+            } else if input.is_key_pressed(Key::Escape) {
+                self.quit = true;
             }
+            return;
+        }
 
-            if self.auto_walk {
-                let dir = (
-                    self.path[0].x - self.player.x,
-                    self.path[0].y - self.player.y,
-                );
-                self.action_manager(Action::Move, engine::orientation(dir));
-                self.path.remove(0);
+        if self.show_history {
+            if input.is_key_pressed(Key::Up) {
+                self.history_scroll += 1;
+            } else if input.is_key_pressed(Key::Down) {
+                self.history_scroll = self.history_scroll.saturating_sub(1);
+            } else if self.keymap.is_pressed(input, Command::History) || input.is_key_pressed(Key::Escape) {
+                self.show_history = false;
             }
+            return;
+        }
+        if self.keymap.is_pressed(input, Command::History) {
+            self.show_history = true;
+            self.history_scroll = 0;
+            return;
+        }
 
-            if input::is_key_pressed(ctx, Key::Left) {
-                self.action_manager(Action::Move, Direction::West);
-            } else if input::is_key_pressed(ctx, Key::Right) {
-                self.action_manager(Action::Move, Direction::East);
-            } else if input::is_key_pressed(ctx, Key::Up) {
-                self.action_manager(Action::Move, Direction::North);
-            } else if input::is_key_pressed(ctx, Key::Down) {
-                self.action_manager(Action::Move, Direction::South);
+        // Run the clock until the player is ready to act
+        let hp = self.player_health().hp;
+        while !scheduler::can_act(&self.world, self.player()) {
+            self.tick();
+            if self.player_health().is_dead() {
+                self.player_death();
+                return;
             }
+        }
 
+        self.look_around();
 
+        // Walking on its own stops as soon as something happens
+        if self.auto_walk || self.exploring {
+            if self.player_health().hp < hp {
+                self.interrupt("You are hurt and stop");
+            } else if let Some(name) = self.new_hostile {
+                self.interrupt(&format!("A {} comes into view, you stop", name));
+            }
         }
-        else {
-            //Monster turn
-            let mut rng = rand::thread_rng();
-            for npc in self.npc_list.iter_mut() {
-                let x: i32 = rng.gen_range(-1..=1);
-                let y: i32 = rng.gen_range(-1..=1);
-                if engine::check_crossable_destination(npc.x, npc.y, engine::orientation((x,y)), &self.floor_map, self.map_width, self.map_height) {
 
-                    engine::move_entity(npc, engine::orientation((x,y)));
+        if let Some(action) = self.inventory_screen {
+            if input.is_key_pressed(Key::Escape) {
+                self.inventory_screen = None;
+            } else if let Some(index) = LETTERS.iter().position(|&k| input.is_key_pressed(k)) {
+                self.inventory_screen = None;
+                match action {
+                    InventoryAction::Use => self.action_manager(Action::Use(index), Direction::None),
+                    InventoryAction::Drop => self.action_manager(Action::Drop(index), Direction::None),
                 }
-                
             }
+            return;
+        }
 
-            self.player_turn = true;
+        // Only search again when the mouse reaches another cell
+        // The path being walked is kept, the camera scrolling under the pointer doesn't change it
+        self.aim_mouse();
+        if self.mouse.cell_moved && !self.auto_walk {
+            self.mouse.cell_moved = false;
+            self.update_path();
+        }
 
-            self.turn += 1;
+        if !self.path.is_empty() && self.mouse.clicked && self.mouse.on_map {
+            self.auto_walk = true;
+            self.exploring = false;
+        } else if let (true, Some(error)) = (self.mouse.clicked, self.path_error) {
+            self.mouse.clicked = false;
+            self.log.info(error);
+        } else if self.path.is_empty() {
+            self.auto_walk = false;
         }
-        if input::is_key_pressed(ctx, Key::Space) {
-            self.floor_map =
-                world::world_genration(self.map_width, self.map_height, GenerationType::Cave);
+
+        if self.auto_walk && !self.next_step_free() {
+            self.replan();
+        }
+
+        if self.auto_walk {
+            let p = self.player_pos();
+            let dir = (self.path[0].x - p.x, self.path[0].y - p.y);
+            self.action_manager(Action::Move, engine::orientation(dir));
+            self.path.remove(0);
+        }
+
+        if self.exploring {
+            self.explore();
+        }
+
+        match self.keymap.pressed(input) {
+            Some(Command::Move(dir)) => self.action_manager(Action::Move, dir),
+            Some(Command::Wait) => self.action_manager(Action::Wait, Direction::None),
+            Some(Command::PickUp) => self.action_manager(Action::PickUp, Direction::None),
+            Some(Command::Descend) => self.action_manager(Action::Descend, Direction::None),
+            Some(Command::Ascend) => self.action_manager(Action::Ascend, Direction::None),
+            Some(Command::Stairs) => {
+                if self.player_tile() == TileType::StairsDown {
+                    self.action_manager(Action::Descend, Direction::None);
+                } else {
+                    self.action_manager(Action::Ascend, Direction::None);
+                }
+            }
+            Some(Command::Explore) => {
+                self.exploring = true;
+                self.auto_walk = false;
+            }
+            Some(Command::Inventory) => self.inventory_screen = Some(InventoryAction::Use),
+            Some(Command::Drop) => self.inventory_screen = Some(InventoryAction::Drop),
+            Some(Command::Tileset) => self.next_tileset = true,
+            Some(Command::Font) => self.switch_font = true,
+            Some(Command::Quit) => {
+                // Saved on the way out by the frontend
+                self.quit = true;
+            }
+            // Handled before the player acts
+            Some(Command::History) | None => {}
         }
-        Ok(())
     }
-    fn event(&mut self, _: &mut Context, event: Event) -> tetra::Result {
+
+    /// Centre the camera on the player and find the world cell under the pointer
+    /// The cell changes when the pointer or the camera moves
+    fn aim_mouse(&mut self) {
+        let p = self.player_pos();
+        self.camera.center_on(p.x, p.y, self.map_width, self.map_height);
+        match self.camera.to_world(self.mouse.screen_x, self.mouse.screen_y) {
+            Some((x, y)) => {
+                if (self.mouse.x, self.mouse.y) != (x, y) {
+                    self.mouse.cell_moved = true;
+                    self.mouse.x = x;
+                    self.mouse.y = y;
+                }
+                self.mouse.on_map = true;
+            }
+            None => self.mouse.on_map = false,
+        }
+    }
+
+    /// Mouse events, positions are turned into screen cells of the given size
+    fn event(&mut self, event: Event, (cell_w, cell_h): (i32, i32)) {
         self.mouse.clicked = false;
         match event {
             Event::MouseMoved { position, .. } => {
-                let new_pos_x = position.x as i32 / CELL_SIZE - UI_SIZE;
-                let new_pos_y = position.y as i32 / CELL_SIZE;
+                self.mouse.screen_x = position.x as i32 / cell_w;
+                self.mouse.screen_y = position.y as i32 / cell_h;
                 self.mouse.active = true;
-                if self.mouse.x != new_pos_x || self.mouse.y != new_pos_y {
-                    self.mouse.cell_moved = true;
-                    self.mouse.x = position.x as i32 / CELL_SIZE - UI_SIZE;
-                    self.mouse.y = position.y as i32 / CELL_SIZE;
-                } else {
-                    self.mouse.cell_moved = false;
-                }
+                self.aim_mouse();
             }
             Event::MouseButtonPressed { button } => {
                 self.mouse.clicked = true;
@@ -270,13 +1237,223 @@ impl State for GameState {
             }
             _ => {}
         }
-        Ok(())
     }
 }
 
+/// Read the options from the command line
+/// `--seed <number>` fixes the seed (random otherwise)
+/// `--permadeath` deletes the save when the hero dies
+/// `--snapshot [text|ansi]` prints the first frame without opening a window
+/// `--tty` plays in the text terminal instead of a window
+/// `--map <width>x<height>` sets the size of the levels of a new game
+fn parse_args() -> Options {
+    let args: Vec<String> = std::env::args().collect();
+    let seed = args
+        .iter()
+        .position(|a| a == "--seed")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|s| s.parse().ok());
+    let fov_algorithm = match args.iter().position(|a| a == "--fov").and_then(|i| args.get(i + 1)) {
+        Some(a) if a == "raycast" => FovAlgorithm::Raycast,
+        _ => FovAlgorithm::Shadowcast,
+    };
+    let snapshot = args.iter().position(|a| a == "--snapshot").map(|i| match args.get(i + 1) {
+        Some(a) if a == "text" => Snapshot::Text,
+        _ => Snapshot::Ansi,
+    });
+    let map_size = args
+        .iter()
+        .position(|a| a == "--map")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|s| {
+            let (w, h) = s.split_once('x')?;
+            Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+        })
+        .map_or((MAP_WIDTH, MAP_HEIGHT), |(w, h): (i32, i32)| {
+            (w.max(MIN_MAP_WIDTH), h.max(MIN_MAP_HEIGHT))
+        });
+    Options {
+        seed,
+        permadeath: args.iter().any(|a| a == "--permadeath"),
+        fov_algorithm,
+        snapshot,
+        tty: args.iter().any(|a| a == "--tty"),
+        map_size,
+    }
+}
+
+/// First frame of a new game, drawn without a window
+fn first_frame(options: &Options) -> Headless {
+    let mut game = GameState::new(options);
+    game.look_around();
+    game.render(0);
+    let mut screen = Headless::new(WIDTH, HEIGHT);
+    screen.present(&mut (), &game.terminal);
+    screen
+}
+
 fn main() -> tetra::Result {
-    ContextBuilder::new("Hello, world!", WIDTH * CELL_SIZE, HEIGHT * CELL_SIZE)
-        .quit_on_escape(true)
+    let options = parse_args();
+    data::load(data::DATA_DIR);
+    if let Some(format) = options.snapshot {
+        let screen = first_frame(&options);
+        match format {
+            Snapshot::Text => print!("{}", screen.text()),
+            Snapshot::Ansi => print!("{}", screen.ansi()),
+        }
+        return Ok(());
+    }
+    if options.tty {
+        let mut game = GameState::new(&options);
+        game.restore();
+        if let Err(e) = tty::run(&mut game, WIDTH, HEIGHT) {
+            eprintln!("Can't play in the terminal: {}", e);
+        }
+        game.save_on_exit();
+        return Ok(());
+    }
+    let tilesets = tileset::load(TILESETS_PATH);
+    let first = &tilesets[0];
+    ContextBuilder::new("Hello, world!", WIDTH * first.cell_w, HEIGHT * first.cell_h)
         .build()?
-        .run(GameState::new)
+        .run(|ctx| {
+            let mut game = GameState::new(&options);
+            game.restore();
+            let mut window = Window::new(ctx, &game.terminal, first)?;
+            match UiFont::load(ctx, UI_FONT_PATH, UI_FONT_SIZE) {
+                Ok(font) => {
+                    window.set_font(Some(font));
+                    game.text_boxes = true;
+                }
+                Err(e) => game.log.info(&format!("Can't load the font {}: {}", UI_FONT_PATH, e)),
+            }
+            Ok(App {
+                game,
+                window,
+                tilesets: tilesets.clone(),
+                tileset: 0,
+                font: None,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn options(seed: u64) -> Options {
+        Options {
+            seed: Some(seed),
+            permadeath: false,
+            fov_algorithm: FovAlgorithm::Shadowcast,
+            snapshot: None,
+            tty: false,
+            map_size: (MAP_WIDTH, MAP_HEIGHT),
+        }
+    }
+
+    /// A game saving in its own file, removed beforehand
+    fn game_saved_in(name: &str, options: &Options) -> GameState {
+        let path = std::env::temp_dir().join(format!("trogue-{}-{}.sav", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut game = GameState::new(options);
+        game.save_path = path.to_str().unwrap().to_string();
+        game
+    }
+
+    fn tiles(floor_map: &[Tile]) -> Vec<(TileType, bool)> {
+        floor_map.iter().map(|t| (t.tile_type, t.visited)).collect()
+    }
+
+    /// Kind, position and hp of every entity, in a stable order
+    fn entities(world: &World) -> Vec<(usize, i32, i32, i32)> {
+        let mut entities: Vec<_> = world
+            .entities()
+            .map(|id| {
+                let p = world.positions.get(id).map_or((-1, -1), |p| (p.x, p.y));
+                let hp = world.healths.get(id).map_or(0, |h| h.hp);
+                (world.kinds.get(id).map_or(0, |k| k.0), p.0, p.1, hp)
+            })
+            .collect();
+        entities.sort_unstable();
+        entities
+    }
+
+    #[test]
+    fn same_seed_same_first_frame() {
+        let a = first_frame(&options(1234)).text();
+        let b = first_frame(&options(1234)).text();
+        assert_eq!(a, b);
+        assert!(a.contains('@'));
+        assert!(a.contains("1234"));
+    }
+
+    #[test]
+    fn seeds_give_other_frames() {
+        assert_ne!(first_frame(&options(1)).text(), first_frame(&options(2)).text());
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let mut game = game_saved_in("round-trip", &options(42));
+        game.change_level(2);
+        game.look_around();
+        for _ in 0..20 {
+            game.tick();
+        }
+        game.rng.gen::<u64>();
+        game.save();
+
+        let mut loaded = game_saved_in("round-trip-other", &options(7));
+        loaded.save_path = game.save_path.clone();
+        loaded.restore();
+        let _ = std::fs::remove_file(&game.save_path);
+
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.depth, 2);
+        assert_eq!(loaded.scheduler.clock, game.scheduler.clock);
+        assert!(tiles(&loaded.floor_map) == tiles(&game.floor_map));
+        assert_eq!(entities(&loaded.world), entities(&game.world));
+        assert!(tiles(&loaded.levels[&1].floor_map) == tiles(&game.levels[&1].floor_map));
+        assert_eq!(entities(&loaded.levels[&1].world), entities(&game.levels[&1].world));
+        // The generator goes on where it was
+        assert_eq!(loaded.rng.gen::<u64>(), game.rng.gen::<u64>());
+    }
+
+    #[test]
+    fn save_with_a_tiny_map_is_rejected() {
+        let mut game = game_saved_in("tiny-map", &options(3));
+        game.save();
+        let text = std::fs::read_to_string(&game.save_path).unwrap();
+        let map_line = format!("map {} {}", MAP_WIDTH, MAP_HEIGHT);
+        std::fs::write(&game.save_path, text.replacen(&map_line, "map 10 8", 1)).unwrap();
+        let result = save::read_save(&game.save_path.clone(), &mut game);
+        let _ = std::fs::remove_file(&game.save_path);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn permadeath_deletes_the_save() {
+        let mut game = game_saved_in("permadeath", &Options {
+            permadeath: true,
+            ..options(1)
+        });
+        game.save();
+        game.player_death();
+        assert!(!std::path::Path::new(&game.save_path).exists());
+    }
+
+    #[test]
+    fn death_keeps_the_last_save_without_permadeath() {
+        let mut game = game_saved_in("death", &options(1));
+        game.save();
+        game.player_death();
+        assert!(std::path::Path::new(&game.save_path).exists());
+
+        // Leaving does not save the dead hero over it
+        std::fs::remove_file(&game.save_path).unwrap();
+        game.save_on_exit();
+        assert!(!std::path::Path::new(&game.save_path).exists());
+    }
 }
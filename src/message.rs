@@ -0,0 +1,144 @@
+use tetra::graphics::Color;
+
+// Keep the log from growing forever
+const MAX_MESSAGES: usize = 200;
+
+pub const TEXT_COLOR: Color = Color::rgb(0.8, 0.8, 0.8);
+pub const DAMAGE_COLOR: Color = Color::rgb(0.85, 0.2, 0.2);
+
+/// Piece of text with its own colour
+#[derive(Clone)]
+pub struct Span {
+    pub text: String,
+    pub color: Color,
+}
+
+impl Span {
+    pub fn new(text: &str, color: Color) -> Span {
+        Span {
+            text: text.to_string(),
+            color,
+        }
+    }
+
+    pub fn text(text: &str) -> Span {
+        Span::new(text, TEXT_COLOR)
+    }
+}
+
+pub struct Message {
+    pub spans: Vec<Span>,
+    // Number of times the message was repeated in a row
+    pub count: u32,
+}
+
+impl Message {
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    /// Spans to display, with the repeat count if any
+    pub fn display_spans(&self) -> Vec<Span> {
+        let mut spans = self.spans.clone();
+        if self.count > 1 {
+            spans.push(Span::text(&format!(" x{}", self.count)));
+        }
+        spans
+    }
+}
+
+pub struct MessageLog {
+    messages: Vec<Message>,
+}
+
+impl MessageLog {
+    pub fn new() -> MessageLog {
+        MessageLog {
+            messages: Vec::new(),
+        }
+    }
+
+    /// Add a message, the same message repeated in a row is just counted
+    pub fn add(&mut self, spans: Vec<Span>) {
+        let text: String = spans.iter().map(|s| s.text.as_str()).collect();
+        if let Some(last) = self.messages.last_mut() {
+            if last.text() == text {
+                last.count += 1;
+                return;
+            }
+        }
+        self.messages.push(Message { spans, count: 1 });
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    pub fn info(&mut self, text: &str) {
+        self.add(vec![Span::text(text)]);
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    /// Every message wrapped to `width`, as lines of spans
+    pub fn lines(&self, width: usize) -> Vec<Vec<Span>> {
+        self.messages
+            .iter()
+            .flat_map(|m| wrap(&m.display_spans(), width))
+            .collect()
+    }
+
+    /// Every message as coloured parts, not wrapped
+    pub fn parts(&self) -> Vec<Vec<(String, Color)>> {
+        self.messages
+            .iter()
+            .map(|m| m.display_spans().into_iter().map(|s| (s.text, s.color)).collect())
+            .collect()
+    }
+}
+
+/// Cut the spans into lines of at most `width` characters
+/// Lines are broken on spaces when possible, colours are kept
+pub fn wrap(spans: &[Span], width: usize) -> Vec<Vec<Span>> {
+    let chars: Vec<(char, Color)> = spans
+        .iter()
+        .flat_map(|s| s.text.chars().map(move |c| (c, s.color)))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = (start + width).min(chars.len());
+        if end < chars.len() {
+            // Break on the last space, the one right after the line counts too
+            if let Some(space) = chars[start..=end].iter().rposition(|c| c.0 == ' ') {
+                if space > 0 {
+                    end = start + space;
+                }
+            }
+        }
+        lines.push(to_spans(&chars[start..end]));
+
+        // No space at the beginning of a line
+        start = end;
+        while start < chars.len() && chars[start].0 == ' ' {
+            start += 1;
+        }
+    }
+    lines
+}
+
+fn to_spans(chars: &[(char, Color)]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    for &(c, color) in chars.iter() {
+        match spans.last_mut() {
+            Some(span) if span.color == color => span.text.push(c),
+            _ => spans.push(Span {
+                text: c.to_string(),
+                color,
+            }),
+        }
+    }
+    spans
+}
@@ -0,0 +1,399 @@
+use crate::ai::{Ai, Behaviour};
+use crate::ecs::{Actor, Bundle, Combat, EntityId, Health, Inventory, Position, World};
+use crate::engine;
+use crate::entity::{EntityType, Item, ItemType, TileType, Tile};
+use crate::world::Level;
+use crate::GameState;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use tetra::graphics::Color;
+
+/// Bump this each time the file layout changes
+const SAVE_VERSION: u32 = 1;
+pub const SAVE_PATH: &str = "./trogue.sav";
+
+// Text format, one record per line:
+//   trogue-save <version>
+//   seed <seed>
+//   rng <word position of the generator>
+//   clock <scheduler clock>
+//   map <width> <height>
+//   depth <current depth>
+//   player <entity>
+//   level <depth>                                  (then, for each level)
+//   tile <type> <fg r g b> <bg r g b> <visited>   (width * height lines)
+//   item <type> <x> <y> <quantity> <equipped>      (lying on the floor)
+//   npc <entity>
+// with <entity> being:
+//   <type> <x> <y> <hp> <max hp> <attack> <defence> <speed> <energy>
+//   <behaviour | -> <last seen x y | ->
+// and after an entity with a pack:
+//   gold <amount>
+//   inv <type> <quantity> <equipped>
+
+pub fn write_save(path: &str, state: &GameState) -> io::Result<()> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+
+    writeln!(file, "trogue-save {}", SAVE_VERSION)?;
+    writeln!(file, "seed {}", state.seed)?;
+    writeln!(file, "rng {}", state.rng.get_word_pos())?;
+    writeln!(file, "clock {}", state.scheduler.clock)?;
+    writeln!(file, "map {} {}", state.map_width, state.map_height)?;
+    writeln!(file, "depth {}", state.depth)?;
+    write_entity(&mut file, "player", &state.world, state.player())?;
+
+    write_level(
+        &mut file,
+        state.depth,
+        &state.floor_map,
+        &state.items,
+        &state.world,
+    )?;
+    let mut depths: Vec<&i32> = state.levels.keys().collect();
+    depths.sort();
+    for depth in depths {
+        let level = &state.levels[depth];
+        write_level(
+            &mut file,
+            level.depth,
+            &level.floor_map,
+            &level.items,
+            &level.world,
+        )?;
+    }
+    file.flush()
+}
+
+/// Replace the game in `state` by the one stored in the file
+pub fn read_save(path: &str, state: &mut GameState) -> io::Result<()> {
+    let file = BufReader::new(fs::File::open(path)?);
+    let mut lines = file.lines();
+    let mut read = || -> io::Result<Option<Vec<String>>> {
+        match lines.next() {
+            Some(line) => Ok(Some(line?.split_whitespace().map(String::from).collect())),
+            None => Ok(None),
+        }
+    };
+
+    let version: u32 = parse(&expect(read()?, "trogue-save")?, 1)?;
+    if version != SAVE_VERSION {
+        return Err(invalid(&format!("unsupported save version {}", version)));
+    }
+    let seed: u64 = parse(&expect(read()?, "seed")?, 1)?;
+    let word_pos: u128 = parse(&expect(read()?, "rng")?, 1)?;
+    let clock: u32 = parse(&expect(read()?, "clock")?, 1)?;
+    let map = expect(read()?, "map")?;
+    let w: i32 = parse(&map, 1)?;
+    let h: i32 = parse(&map, 2)?;
+    // Same bounds as the --map option, the generators can't fill a smaller map
+    if w < crate::MIN_MAP_WIDTH || h < crate::MIN_MAP_HEIGHT || w.checked_mul(h).is_none() {
+        return Err(invalid(&format!("bad map size {} x {}", w, h)));
+    }
+    let depth: i32 = parse(&expect(read()?, "depth")?, 1)?;
+    let mut player = read_entity(&expect(read()?, "player")?, w, h)?;
+    player.player = true;
+
+    // Tile colours and gold amounts come from the file, so a throwaway generator is enough here
+    let mut tmp_rng = engine::new_rng(0);
+    let mut levels: Vec<Level> = Vec::new();
+    // Npc of each level, spawned once their pack is read too
+    let mut npcs: Vec<Vec<Bundle>> = Vec::new();
+    while let Some(fields) = read()? {
+        match field(&fields, 0) {
+            "level" => {
+                let mut level = Level {
+                    depth: parse(&fields, 1)?,
+                    floor_map: Vec::new(),
+                    world: World::new(),
+                    items: Vec::new(),
+                };
+                for i in 0..w * h {
+                    let fields = expect(read()?, "tile")?;
+                    let tile_type = tile_from_code(field(&fields, 1))?;
+                    let mut tile = Tile::new(i % w, i / w, tile_type, &mut tmp_rng);
+                    tile.fg_color = parse_color(&fields, 2)?;
+                    tile.bg_color = parse_color(&fields, 5)?;
+                    tile.visited = parse::<u8>(&fields, 8)? == 1;
+                    level.floor_map.push(tile);
+                }
+                levels.push(level);
+                npcs.push(Vec::new());
+            }
+            "item" => {
+                let level = levels.last_mut().ok_or_else(|| invalid(&fields.join(" ")))?;
+                let (x, y) = check_position(&fields, 2, w, h)?;
+                let mut item = Item::new(x, y, item_from_code(field(&fields, 1))?, &mut tmp_rng);
+                item.quantity = parse(&fields, 4)?;
+                item.equipped = parse::<u8>(&fields, 5)? == 1;
+                level.items.push(item);
+            }
+            "npc" => {
+                let level = npcs.last_mut().ok_or_else(|| invalid(&fields.join(" ")))?;
+                level.push(read_entity(&fields, w, h)?);
+            }
+            "gold" | "inv" => {
+                // Belongs to the last entity read
+                let owner = match npcs.last_mut() {
+                    Some(level) => level
+                        .last_mut()
+                        .ok_or_else(|| invalid(&fields.join(" ")))?,
+                    None => &mut player,
+                };
+                read_entity_extra(&fields, owner, &mut tmp_rng)?;
+            }
+            _ => return Err(invalid(&fields.join(" "))),
+        }
+    }
+
+    for (level, bundles) in levels.iter_mut().zip(npcs) {
+        for bundle in bundles {
+            level.world.spawn(bundle);
+        }
+    }
+    let current = levels
+        .iter()
+        .position(|l| l.depth == depth)
+        .ok_or_else(|| invalid("no current level"))?;
+    let mut level = levels.remove(current);
+    level.world.spawn(player);
+
+    state.seed = seed;
+    state.rng = engine::new_rng(seed);
+    state.rng.set_word_pos(word_pos);
+    state.scheduler.clock = clock;
+    state.map_width = w;
+    state.map_height = h;
+    state.depth = depth;
+    state.floor_map = level.floor_map;
+    state.world = level.world;
+    state.items = level.items;
+    state.levels = levels.into_iter().map(|l| (l.depth, l)).collect();
+
+    Ok(())
+}
+
+pub fn delete_save(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn write_level(
+    file: &mut impl Write,
+    depth: i32,
+    floor_map: &[Tile],
+    items: &[Item],
+    world: &World,
+) -> io::Result<()> {
+    writeln!(file, "level {}", depth)?;
+    for tile in floor_map.iter() {
+        writeln!(
+            file,
+            "tile {} {} {} {}",
+            tile.tile_type.code(),
+            color_code(tile.fg_color),
+            color_code(tile.bg_color),
+            tile.visited as u8
+        )?;
+    }
+    for item in items.iter() {
+        writeln!(
+            file,
+            "item {} {} {} {} {}",
+            item.item.def().id,
+            item.x,
+            item.y,
+            item.quantity,
+            item.equipped as u8
+        )?;
+    }
+    // The player is saved on its own
+    for id in world.entities().filter(|&id| !world.is_player(id)) {
+        write_entity(file, "npc", world, id)?;
+    }
+    Ok(())
+}
+
+fn write_entity(file: &mut impl Write, tag: &str, world: &World, id: EntityId) -> io::Result<()> {
+    let (kind, p, health, combat, actor) = match (
+        world.kinds.get(id),
+        world.positions.get(id),
+        world.healths.get(id),
+        world.combats.get(id),
+        world.actors.get(id),
+    ) {
+        (Some(kind), Some(p), Some(health), Some(combat), Some(actor)) => {
+            (kind, p, health, combat, actor)
+        }
+        _ => return Err(invalid(&format!("{} is missing a component", tag))),
+    };
+    let (behaviour, last_seen) = match world.ais.get(id) {
+        Some(ai) => (
+            behaviour_code(ai.behaviour),
+            match ai.last_seen {
+                Some((x, y)) => format!("{} {}", x, y),
+                None => "-".to_string(),
+            },
+        ),
+        None => ("-", "-".to_string()),
+    };
+    writeln!(
+        file,
+        "{} {} {} {} {} {} {} {} {} {} {} {}",
+        tag,
+        kind.def().id,
+        p.x,
+        p.y,
+        health.hp,
+        health.max_hp,
+        combat.attack,
+        combat.defence,
+        actor.speed,
+        actor.energy,
+        behaviour,
+        last_seen
+    )?;
+    if let Some(inventory) = world.inventories.get(id) {
+        writeln!(file, "gold {}", inventory.gold)?;
+        for item in inventory.items.iter() {
+            writeln!(
+                file,
+                "inv {} {} {}",
+                item.item.def().id,
+                item.quantity,
+                item.equipped as u8
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn read_entity(fields: &[String], w: i32, h: i32) -> io::Result<Bundle> {
+    let (x, y) = check_position(fields, 2, w, h)?;
+    let mut e = Bundle::creature(x, y, entity_from_code(field(fields, 1))?);
+    e.position = Some(Position { x, y });
+    e.health = Some(Health {
+        hp: parse(fields, 4)?,
+        max_hp: at_least_one(fields, 5)?,
+    });
+    e.combat = Some(Combat {
+        attack: parse(fields, 6)?,
+        defence: parse(fields, 7)?,
+    });
+    // A still entity would hold the clock forever
+    e.actor = Some(Actor {
+        speed: at_least_one(fields, 8)?,
+        energy: parse(fields, 9)?,
+    });
+    e.ai = match field(fields, 10) {
+        "-" => None,
+        code => {
+            let mut ai = Ai::new();
+            ai.behaviour = behaviour_from_code(code)?;
+            if field(fields, 11) != "-" {
+                ai.last_seen = Some(check_position(fields, 11, w, h)?);
+            }
+            Some(ai)
+        }
+    };
+    Ok(e)
+}
+
+/// Gold and inventory lines of an entity
+fn read_entity_extra(fields: &[String], owner: &mut Bundle, rng: &mut engine::GameRng) -> io::Result<()> {
+    let inventory = owner.inventory.get_or_insert_with(Inventory::default);
+    if field(fields, 0) == "gold" {
+        inventory.gold = parse(fields, 1)?;
+        return Ok(());
+    }
+    let mut item = Item::new(0, 0, item_from_code(field(fields, 1))?, rng);
+    item.quantity = parse(fields, 2)?;
+    item.equipped = parse::<u8>(fields, 3)? == 1;
+    inventory.items.push(item);
+    Ok(())
+}
+
+fn expect(fields: Option<Vec<String>>, tag: &str) -> io::Result<Vec<String>> {
+    match fields {
+        Some(fields) if field(&fields, 0) == tag => Ok(fields),
+        Some(fields) => Err(invalid(&format!("expected `{}`, found `{}`", tag, fields.join(" ")))),
+        None => Err(invalid("unexpected end of file")),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("bad save file: {}", msg))
+}
+
+fn field(fields: &[String], i: usize) -> &str {
+    fields.get(i).map_or("", |f| f.as_str())
+}
+
+fn parse<T: std::str::FromStr>(fields: &[String], i: usize) -> io::Result<T> {
+    fields
+        .get(i)
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| invalid(&fields.join(" ")))
+}
+
+/// Same bound as for the hp and speed of the data files
+fn at_least_one(fields: &[String], i: usize) -> io::Result<i32> {
+    parse(fields, i)
+        .ok()
+        .filter(|&n: &i32| n >= 1)
+        .ok_or_else(|| invalid(&fields.join(" ")))
+}
+
+/// Position read from two fields, which must be on the map
+fn check_position(fields: &[String], i: usize, w: i32, h: i32) -> io::Result<(i32, i32)> {
+    let (x, y): (i32, i32) = (parse(fields, i)?, parse(fields, i + 1)?);
+    if !engine::is_in_map(x, y, w, h) {
+        return Err(invalid(&format!("off the map: {}", fields.join(" "))));
+    }
+    Ok((x, y))
+}
+
+fn color_code(c: Color) -> String {
+    let to_u8 = |v: f32| (v * 255.0).round() as u8;
+    format!("{} {} {}", to_u8(c.r), to_u8(c.g), to_u8(c.b))
+}
+
+fn parse_color(fields: &[String], i: usize) -> io::Result<Color> {
+    Ok(Color::rgb8(
+        parse(fields, i)?,
+        parse(fields, i + 1)?,
+        parse(fields, i + 2)?,
+    ))
+}
+
+fn tile_from_code(code: &str) -> io::Result<TileType> {
+    TileType::from_code(code).ok_or_else(|| invalid(&format!("unknown tile `{}`", code)))
+}
+
+fn item_from_code(code: &str) -> io::Result<ItemType> {
+    ItemType::from_id(code).ok_or_else(|| invalid(&format!("unknown item `{}`", code)))
+}
+
+fn entity_from_code(code: &str) -> io::Result<EntityType> {
+    EntityType::from_id(code).ok_or_else(|| invalid(&format!("unknown entity `{}`", code)))
+}
+
+fn behaviour_code(behaviour: Behaviour) -> &'static str {
+    match behaviour {
+        Behaviour::Wander => "wander",
+        Behaviour::Hunt => "hunt",
+        Behaviour::Flee => "flee",
+        Behaviour::Search => "search",
+    }
+}
+
+fn behaviour_from_code(code: &str) -> io::Result<Behaviour> {
+    match code {
+        "wander" => Ok(Behaviour::Wander),
+        "hunt" => Ok(Behaviour::Hunt),
+        "flee" => Ok(Behaviour::Flee),
+        "search" => Ok(Behaviour::Search),
+        _ => Err(invalid(&format!("unknown behaviour `{}`", code))),
+    }
+}
@@ -0,0 +1,108 @@
+use crate::engine::Action;
+use crate::ecs::{EntityId, World};
+
+/// Energy an entity needs before it can act
+pub const ACTION_ENERGY: i32 = 100;
+/// Energy gained on each tick at normal speed
+pub const NORMAL_SPEED: i32 = 10;
+// A normal speed entity acts once per turn
+const TICKS_PER_TURN: u32 = (ACTION_ENERGY / NORMAL_SPEED) as u32;
+
+/// Energy spent by each action
+pub fn action_cost(action: &Action) -> i32 {
+    match action {
+        Action::Move => 100,
+        Action::Attack => 100,
+        Action::Wait => 50,
+        Action::Descend => 100,
+        Action::Ascend => 100,
+        Action::PickUp => 100,
+        Action::Drop(_) => 50,
+        Action::Use(_) => 100,
+    }
+}
+
+pub fn can_act(world: &World, id: EntityId) -> bool {
+    world.actors.get(id).is_some_and(|a| a.energy >= ACTION_ENERGY)
+}
+
+pub fn spend(world: &mut World, id: EntityId, action: &Action) {
+    if let Some(actor) = world.actors.get_mut(id) {
+        actor.energy -= action_cost(action);
+    }
+}
+
+/// Energy based scheduler
+/// On each tick every entity gains its speed in energy,
+/// those with enough energy act and pay for their action
+pub struct Scheduler {
+    pub clock: u32,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler { clock: 0 }
+    }
+
+    /// Game turn, derived from the clock
+    pub fn turn(&self) -> u32 {
+        self.clock / TICKS_PER_TURN
+    }
+
+    pub fn tick(&mut self, world: &mut World) {
+        self.clock += 1;
+        for (_, actor) in world.actors.iter_mut() {
+            actor.energy += actor.speed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::{Actor, Bundle};
+    use crate::entity::EntityType;
+
+    /// Run the scheduler for some turns, with an entity for each speed
+    /// repeating `action` as long as it can, return how many actions each one made
+    fn actions_in(turns: u32, speeds: &[i32], action: Action) -> Vec<u32> {
+        let mut world = World::new();
+        let ids: Vec<EntityId> = speeds
+            .iter()
+            .map(|&speed| {
+                let mut bundle = Bundle::creature(0, 0, EntityType::player());
+                // Ready to act on the first tick
+                bundle.actor = Some(Actor {
+                    speed,
+                    energy: ACTION_ENERGY - speed,
+                });
+                world.spawn(bundle)
+            })
+            .collect();
+
+        let mut scheduler = Scheduler::new();
+        let mut done = vec![0; ids.len()];
+        while scheduler.turn() < turns {
+            scheduler.tick(&mut world);
+            for (&id, n) in ids.iter().zip(done.iter_mut()) {
+                while can_act(&world, id) {
+                    spend(&mut world, id, &action);
+                    *n += 1;
+                }
+            }
+        }
+        done
+    }
+
+    #[test]
+    fn double_speed_acts_twice_as_often() {
+        assert_eq!(actions_in(10, &[NORMAL_SPEED, 2 * NORMAL_SPEED], Action::Move), vec![10, 20]);
+    }
+
+    #[test]
+    fn wait_and_drop_cost_half_an_action() {
+        assert_eq!(action_cost(&Action::Wait), 50);
+        assert_eq!(action_cost(&Action::Drop(0)), 50);
+        assert_eq!(actions_in(10, &[NORMAL_SPEED], Action::Wait), vec![20]);
+    }
+}
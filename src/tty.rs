@@ -0,0 +1,184 @@
+use crate::grl::{Ansi, Backend};
+use crate::keymap::Input;
+use crate::{GameState, LETTERS};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use tetra::input::Key;
+
+/// Raw mode of the text terminal, set back as it was when dropped
+struct RawMode {
+    saved: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        // SAFETY: termios is plain data, filled by tcgetattr before use
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            // Reads give up after a tenth of a second, so the game keeps running
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawMode { saved })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: saved was filled by tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Play in the text terminal until the game asks to quit or Ctrl-C is pressed
+/// Saving on the way out is left to the caller
+pub fn run(game: &mut GameState, w: i32, h: i32) -> io::Result<()> {
+    let _raw = RawMode::enable()?;
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut screen = Ansi::new(w, h);
+    // Alternate screen, hidden cursor
+    write!(stdout, "\x1b[?1049h\x1b[?25l\x1b[2J")?;
+
+    let mut fps = 0;
+    let mut frames = 0;
+    let mut second = Instant::now();
+    let mut buffer = [0; 64];
+    let result = loop {
+        let mut read = match stdin.read(&mut buffer) {
+            Ok(read) => read,
+            Err(e) => break Err(e),
+        };
+        // A sequence may be cut by the read timeout, the rest comes with the next read
+        // An escape is only taken as the key once that read comes back empty
+        if cut_escape(&buffer[..read]) && read < buffer.len() {
+            match stdin.read(&mut buffer[read..]) {
+                Ok(more) => read += more,
+                Err(e) => break Err(e),
+            }
+        }
+        if interrupted(&buffer[..read]) {
+            break Ok(());
+        }
+        game.update(&Input::new(decode(&buffer[..read])));
+        if game.quit {
+            break Ok(());
+        }
+
+        game.render(fps);
+        let mut out = String::new();
+        screen.present(&mut out, &game.terminal);
+        if let Err(e) = stdout.write_all(out.as_bytes()).and_then(|_| stdout.flush()) {
+            break Err(e);
+        }
+
+        frames += 1;
+        if second.elapsed() >= Duration::from_secs(1) {
+            fps = frames;
+            frames = 0;
+            second = Instant::now();
+        }
+    };
+
+    write!(stdout, "\x1b[0m\x1b[?25h\x1b[?1049l")?;
+    stdout.flush()?;
+    result
+}
+
+/// Ctrl-C, which raw mode hands over as a byte, quits as the Quit command does
+fn interrupted(bytes: &[u8]) -> bool {
+    bytes.contains(&0x03)
+}
+
+/// The bytes end with an escape or the start of an escape sequence
+fn cut_escape(bytes: &[u8]) -> bool {
+    matches!(bytes, [.., 0x1b] | [.., 0x1b, b'['])
+}
+
+/// Keys sent by the text terminal
+/// Digits are taken as the numpad, for the moves
+fn decode(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            // Arrows are sent as `ESC [ A` to `ESC [ D`
+            0x1b if bytes.get(i + 1) == Some(&b'[') => {
+                i += 2;
+                match bytes.get(i) {
+                    Some(b'A') => Some(Key::Up),
+                    Some(b'B') => Some(Key::Down),
+                    Some(b'C') => Some(Key::Right),
+                    Some(b'D') => Some(Key::Left),
+                    _ => None,
+                }
+            }
+            // Alt and a key, not bound to anything
+            0x1b if i + 1 < bytes.len() => {
+                i += 1;
+                None
+            }
+            0x1b => Some(Key::Escape),
+            b'\r' | b'\n' => Some(Key::Enter),
+            b'.' => Some(Key::Period),
+            b',' => Some(Key::Comma),
+            b'<' => Some(Key::LessThan),
+            b'>' => Some(Key::GreaterThan),
+            c @ b'0'..=b'9' => Some(NUMPAD[(c - b'0') as usize]),
+            c if c.is_ascii_alphabetic() => Some(LETTERS[(c.to_ascii_lowercase() - b'a') as usize]),
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+const NUMPAD: [Key; 10] = [
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arrows_and_letters() {
+        assert!(decode(b"\x1b[Ak") == vec![Key::Up, Key::K]);
+        assert!(decode(b"5") == vec![Key::NumPad5]);
+    }
+
+    #[test]
+    fn escape_alone_only() {
+        assert!(decode(b"\x1b") == vec![Key::Escape]);
+        // Alt+x
+        assert!(decode(b"\x1bx").is_empty());
+    }
+
+    #[test]
+    fn ctrl_c_quits() {
+        assert!(interrupted(b"k\x03"));
+        assert!(!interrupted(b"\x1b[A"));
+        // Not a key for the game
+        assert!(decode(b"\x03").is_empty());
+    }
+
+    #[test]
+    fn cut_sequences_wait_for_more() {
+        assert!(cut_escape(b"k\x1b"));
+        assert!(cut_escape(b"\x1b["));
+        assert!(!cut_escape(b"\x1b[A"));
+        assert!(!cut_escape(b""));
+    }
+}
@@ -1,34 +1,135 @@
-use crate::entity::{Entity, EntityType,ItemType, Tile};
+use crate::engine::GameRng;
+use crate::data;
+use crate::ecs::{Bundle, World};
+use crate::entity::{EntityType, Item, ItemType, TileType, Tile};
 use rand::prelude::*;
 
+#[derive(Copy, Clone)]
 pub enum GenerationType {
     Random,
     Cave,
     Floor,
 }
 
-pub fn world_genration(w: i32, h: i32, gen_type: GenerationType) -> Vec<Tile> {
+/// One depth of the dungeon, kept as is when the player leaves it
+pub struct Level {
+    pub depth: i32,
+    pub floor_map: Vec<Tile>,
+    pub world: World,
+    pub items: Vec<Item>,
+}
+
+/// Generate a new level with its stairs, npc and items
+/// Caves and floors alternate with depth
+pub fn generate_level(depth: i32, w: i32, h: i32, rng: &mut GameRng) -> Level {
+    let gen_type = if depth % 2 == 1 {
+        GenerationType::Cave
+    } else {
+        GenerationType::Floor
+    };
+    // Both stairs in the biggest open area, so they are linked
+    // A level without room for both is generated again, an open one is the last resort
+    const MAX_TRY: usize = 10;
+    let mut floor_map = world_genration(w, h, gen_type, rng);
+    let mut region = largest_region(&floor_map, w, h);
+    let mut tries = 1;
+    while region.len() < 2 {
+        floor_map = if tries < MAX_TRY {
+            world_genration(w, h, gen_type, rng)
+        } else {
+            random_level(w, h, rng)
+        };
+        region = largest_region(&floor_map, w, h);
+        tries += 1;
+    }
+    let up = region[rng.gen_range(0..region.len())];
+    let mut down = up;
+    while down == up {
+        down = region[rng.gen_range(0..region.len())];
+    }
+    floor_map[up] = Tile::new(up as i32 % w, up as i32 / w, TileType::StairsUp, rng);
+    floor_map[down] = Tile::new(down as i32 % w, down as i32 / w, TileType::StairsDown, rng);
+
+    // Keep the arrival free
+    let mut world = World::new();
+    let arrival = (up as i32 % w, up as i32 / w);
+    spawn_npc(&floor_map, &mut world, arrival, depth, w, h, rng);
+    let items = spawn_items(&floor_map, w, h, rng);
+
+    Level {
+        depth,
+        floor_map,
+        world,
+        items,
+    }
+}
+
+/// Indexes of the biggest set of connected crossable tiles
+fn largest_region(level_map: &[Tile], w: i32, h: i32) -> Vec<usize> {
+    let mut region_of: Vec<Option<usize>> = vec![None; (w * h) as usize];
+    let mut best: Vec<usize> = Vec::new();
+    for start in 0..(w * h) as usize {
+        if !level_map[start].crossable || region_of[start].is_some() {
+            continue;
+        }
+        // Flood fill
+        let mut region = vec![start];
+        region_of[start] = Some(start);
+        let mut i = 0;
+        while i < region.len() {
+            let (x, y) = (region[i] as i32 % w, region[i] as i32 / w);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || nx >= w || ny < 0 || ny >= h {
+                        continue;
+                    }
+                    let n = (nx + ny * w) as usize;
+                    if level_map[n].crossable && region_of[n].is_none() {
+                        region_of[n] = Some(start);
+                        region.push(n);
+                    }
+                }
+            }
+            i += 1;
+        }
+        if region.len() > best.len() {
+            best = region;
+        }
+    }
+
+    best
+}
+
+/// Position of the first tile of the given type
+pub fn find_tile(level_map: &[Tile], tile_type: TileType) -> Option<(i32, i32)> {
+    level_map
+        .iter()
+        .find(|t| t.tile_type == tile_type)
+        .map(|t| (t.x, t.y))
+}
+
+pub fn world_genration(w: i32, h: i32, gen_type: GenerationType, rng: &mut GameRng) -> Vec<Tile> {
     let mut level = Vec::new();
     match gen_type {
-        GenerationType::Random => level = random_level(w, h),
-        GenerationType::Cave => level = cave_level(w, h),
-        GenerationType::Floor => level = floor_level(w, h),
+        GenerationType::Random => level = random_level(w, h, rng),
+        GenerationType::Cave => level = cave_level(w, h, rng),
+        GenerationType::Floor => level = floor_level(w, h, rng),
     }
 
     level
 }
 
-pub fn random_level(w: i32, h: i32) -> Vec<Tile> {
-    let mut rng = rand::thread_rng();
+pub fn random_level(w: i32, h: i32, rng: &mut GameRng) -> Vec<Tile> {
     let mut level_map = Vec::new();
     for i in 0..(w * h) as usize {
         let alea: u8 = rng.gen_range(0..=100);
         let tile: Tile;
 
         if alea < 1 {
-            tile = Tile::new(i as i32 % w, i as i32 / w, ItemType::StoneWall);
+            tile = Tile::new(i as i32 % w, i as i32 / w, TileType::StoneWall, rng);
         } else {
-            tile = Tile::new(i as i32 % w, i as i32 / w, ItemType::StoneFloor);
+            tile = Tile::new(i as i32 % w, i as i32 / w, TileType::StoneFloor, rng);
         }
         level_map.push(tile);
     }
@@ -36,8 +137,7 @@ pub fn random_level(w: i32, h: i32) -> Vec<Tile> {
     level_map
 }
 
-pub fn cave_level(w: i32, h: i32) -> Vec<Tile> {
-    let mut rng = rand::thread_rng();
+pub fn cave_level(w: i32, h: i32, rng: &mut GameRng) -> Vec<Tile> {
 
     // Sector division
     // 16/12/5 => Labyrinthe, peu de salles, quelques zone isolées
@@ -54,7 +154,7 @@ pub fn cave_level(w: i32, h: i32) -> Vec<Tile> {
 
     //Fill map with Wall
     for _i in 0..(w * h) as usize {
-        draft.push(ItemType::MudWall);
+        draft.push(TileType::MudWall);
     }
 
     // seeding each sector
@@ -63,7 +163,7 @@ pub fn cave_level(w: i32, h: i32) -> Vec<Tile> {
         for j in 0..v_cells {
             let x = i as i32 * width + rng.gen_range(1..width);
             let y = j as i32 * height + rng.gen_range(1..height);
-            draft[(x + y * w) as usize] = ItemType::StoneFloor;
+            draft[(x + y * w) as usize] = TileType::StoneFloor;
         }
     }
 
@@ -79,13 +179,13 @@ pub fn cave_level(w: i32, h: i32) -> Vec<Tile> {
     while cycle > 0 {
         for i in 0..(w * h) as usize {
             match draft[i] {
-                ItemType::StoneFloor => {
-                    temp[i] = ItemType::StoneFloor;
+                TileType::StoneFloor => {
+                    temp[i] = TileType::StoneFloor;
                     let new_index = (i as i32) + direction[rng.gen_range(0..4)];
                     let x = new_index % w;
                     let y = new_index / w;
                     if x >= 0 && x < w && y >= 0 && y < h {
-                        temp[new_index as usize] = ItemType::StoneFloor;
+                        temp[new_index as usize] = TileType::StoneFloor;
                     }
                 }
                 _ => {}
@@ -98,43 +198,257 @@ pub fn cave_level(w: i32, h: i32) -> Vec<Tile> {
     }
 
     for i in 0..(w * h) as usize {
-        level_map.push(Tile::new(i as i32 % w, i as i32 / w, draft[i]));
+        level_map.push(Tile::new(i as i32 % w, i as i32 / w, draft[i], rng));
     }
 
     level_map
 }
 
-pub fn floor_level(w: i32, h: i32) -> Vec<Tile> {
-    let mut rng = rand::thread_rng();
-    let mut level_map = Vec::new();
+/// Rectangular room of a floor level (interior only, walls excluded)
+struct Room {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Room {
+    fn center(&self) -> (i32, i32) {
+        (self.x + self.w / 2, self.y + self.h / 2)
+    }
+
+    /// True if the two rooms are closer than `margin` cells
+    fn intersects(&self, other: &Room, margin: i32) -> bool {
+        self.x - margin < other.x + other.w
+            && self.x + self.w + margin > other.x
+            && self.y - margin < other.y + other.h
+            && self.y + self.h + margin > other.y
+    }
+}
 
-    // First, the Corridors
+pub fn floor_level(w: i32, h: i32, rng: &mut GameRng) -> Vec<Tile> {
 
-    // Choose starting point
-    let x: i32 = rng.gen_range(5..(w - 5));
-    let y: i32 = rng.gen_range(5..(h - 5));
+    const MAX_ROOMS: usize = 20;
+    const MAX_TRY: usize = 300;
 
-    let dir = [-1, 1, -w, w][rng.gen_range(0..4)];
+    let mut level_map = Vec::new();
+    let mut draft = Vec::new();
 
-    let length = rng.gen_range(5..10);
+    //Fill map with Wall
+    for _i in 0..(w * h) as usize {
+        draft.push(TileType::StoneWall);
+    }
+
+    // First, the rooms
+    // A margin of 2 keeps the walls of two rooms from touching
+    let mut rooms: Vec<Room> = Vec::new();
+    for _i in 0..MAX_TRY {
+        if rooms.len() >= MAX_ROOMS {
+            break;
+        }
+        let room_w = rng.gen_range(4..12);
+        let room_h = rng.gen_range(3..8);
+        let room = Room {
+            x: rng.gen_range(1..(w - room_w - 1)),
+            y: rng.gen_range(1..(h - room_h - 1)),
+            w: room_w,
+            h: room_h,
+        };
+        if rooms.iter().any(|r| r.intersects(&room, 2)) {
+            continue;
+        }
+        for x in room.x..(room.x + room.w) {
+            for y in room.y..(room.y + room.h) {
+                draft[(x + y * w) as usize] = TileType::StoneFloor;
+            }
+        }
+        rooms.push(room);
+    }
+
+    // Then, the Corridors
+    // Each room is linked to the previous one, so every room is reachable
+    for i in 1..rooms.len() {
+        let (x1, y1) = rooms[i - 1].center();
+        let (x2, y2) = rooms[i].center();
+        if rng.gen_bool(0.5) {
+            dig_h_corridor(&mut draft, w, x1, x2, y1);
+            dig_v_corridor(&mut draft, w, y1, y2, x2);
+        } else {
+            dig_v_corridor(&mut draft, w, y1, y2, x1);
+            dig_h_corridor(&mut draft, w, x1, x2, y2);
+        }
+    }
 
-    // Choose direction length
+    // Finally, the doors
+    // A door is set where a corridor goes through the wall of a room
+    for room in rooms.iter() {
+        for x in room.x..(room.x + room.w) {
+            set_door(&mut draft, w, x, room.y - 1, (1, 0));
+            set_door(&mut draft, w, x, room.y + room.h, (1, 0));
+        }
+        for y in room.y..(room.y + room.h) {
+            set_door(&mut draft, w, room.x - 1, y, (0, 1));
+            set_door(&mut draft, w, room.x + room.w, y, (0, 1));
+        }
+    }
 
-    // Draw corridoe
+    for (i, &tile_type) in draft.iter().enumerate() {
+        level_map.push(Tile::new(i as i32 % w, i as i32 / w, tile_type, rng));
+    }
 
     level_map
 }
 
-pub fn spawn_npc(level_map: &Vec<Tile>, w: i32, h: i32) -> Vec<Entity> {
-    let mut npc = Vec::new();
-    let mut rng = rand::thread_rng();
-    for _i in 0..20 {
+fn dig_h_corridor(draft: &mut [TileType], w: i32, x1: i32, x2: i32, y: i32) {
+    for x in x1.min(x2)..=x1.max(x2) {
+        draft[(x + y * w) as usize] = TileType::StoneFloor;
+    }
+}
+
+fn dig_v_corridor(draft: &mut [TileType], w: i32, y1: i32, y2: i32, x: i32) {
+    for y in y1.min(y2)..=y1.max(y2) {
+        draft[(x + y * w) as usize] = TileType::StoneFloor;
+    }
+}
+
+/// Turn a wall opening into a door
+/// `along` is the direction of the wall, so a corridor running
+/// along the wall doesn't become a line of doors
+fn set_door(draft: &mut [TileType], w: i32, x: i32, y: i32, along: (i32, i32)) {
+    let is_wall = |i: i32| matches!(draft[i as usize], TileType::StoneWall);
+    let index = x + y * w;
+    let before = index - along.0 - along.1 * w;
+    let after = index + along.0 + along.1 * w;
+    if !is_wall(index) && is_wall(before) && is_wall(after) {
+        draft[index as usize] = TileType::Door;
+    }
+}
+
+/// Spawn npc on free crossable cells, away from the arrival
+/// The deeper, the more numerous and tougher they are
+pub fn spawn_npc(
+    level_map: &[Tile],
+    world: &mut World,
+    arrival: (i32, i32),
+    depth: i32,
+    w: i32,
+    h: i32,
+    rng: &mut GameRng,
+) {
+    for _i in 0..(15 + 5 * depth) {
         let index = rng.gen_range(0..(w*h));
+        let (x, y) = (index % w, index / w);
+        if level_map[index as usize].crossable
+            && (x, y) != arrival
+            && world.blocker_at(x, y).is_none()
+        {
+            if let Some(kind) = data::registry().random_entity(rng) {
+                let mut npc = Bundle::creature(x, y, EntityType(kind));
+                npc.scale_to_depth(depth);
+                world.spawn(npc);
+            }
+        }
+
+    }
+}
+
+/// Scatter items on crossable cells
+pub fn spawn_items(level_map: &[Tile], w: i32, h: i32, rng: &mut GameRng) -> Vec<Item> {
+    let mut items = Vec::new();
+    for _i in 0..15 {
+        let index = rng.gen_range(0..(w * h));
         if level_map[index as usize].crossable {
-            npc.push(Entity::new(index % w, index / w, EntityType::Zombie));
+            if let Some(i) = data::registry().random_item(rng) {
+                items.push(Item::new(index % w, index / w, ItemType(i), rng));
+            }
+        }
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine;
+    use tetra::graphics::Color;
+
+    const W: i32 = 64;
+    const H: i32 = 48;
+
+    fn layout(level_map: &[Tile]) -> Vec<(char, bool, Color, Color)> {
+        level_map
+            .iter()
+            .map(|t| (t.glyph, t.crossable, t.fg_color, t.bg_color))
+            .collect()
+    }
+
+    /// Cells reachable from `from` through crossable tiles
+    fn reachable(level_map: &[Tile], from: (i32, i32)) -> Vec<bool> {
+        let mut seen = vec![false; (W * H) as usize];
+        let mut todo = vec![from];
+        seen[(from.0 + from.1 * W) as usize] = true;
+        while let Some((x, y)) = todo.pop() {
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if !(0..W).contains(&nx) || !(0..H).contains(&ny) {
+                        continue;
+                    }
+                    let n = (nx + ny * W) as usize;
+                    if level_map[n].crossable && !seen[n] {
+                        seen[n] = true;
+                        todo.push((nx, ny));
+                    }
+                }
+            }
         }
+        seen
+    }
 
+    #[test]
+    fn same_seed_same_floor() {
+        let a = floor_level(W, H, &mut engine::new_rng(42));
+        let b = floor_level(W, H, &mut engine::new_rng(42));
+        assert!(layout(&a) == layout(&b));
     }
 
-    npc
-}
\ No newline at end of file
+    #[test]
+    fn same_seed_same_cave() {
+        let a = cave_level(W, H, &mut engine::new_rng(42));
+        let b = cave_level(W, H, &mut engine::new_rng(42));
+        assert!(layout(&a) == layout(&b));
+    }
+
+    #[test]
+    fn other_seed_other_cave() {
+        let a = cave_level(W, H, &mut engine::new_rng(1));
+        let b = cave_level(W, H, &mut engine::new_rng(2));
+        assert!(layout(&a) != layout(&b));
+    }
+
+    #[test]
+    fn stairs_are_linked() {
+        for seed in 0..10 {
+            // Odd depths are caves, even ones floors
+            for depth in 1..=4 {
+                let level = generate_level(depth, W, H, &mut engine::new_rng(seed));
+                let up = find_tile(&level.floor_map, TileType::StairsUp).expect("no stairs up");
+                let down = find_tile(&level.floor_map, TileType::StairsDown).expect("no stairs down");
+                let seen = reachable(&level.floor_map, up);
+                assert!(seen[(down.0 + down.1 * W) as usize], "seed {} depth {}", seed, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn small_maps_get_stairs() {
+        for seed in 0..20 {
+            for depth in 1..=4 {
+                let level = generate_level(depth, 20, 16, &mut engine::new_rng(seed));
+                assert!(find_tile(&level.floor_map, TileType::StairsUp).is_some());
+                assert!(find_tile(&level.floor_map, TileType::StairsDown).is_some());
+            }
+        }
+    }
+}